@@ -1,9 +1,17 @@
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
+use std::borrow::Cow;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 
 use anyhow::{bail, Result};
+use regex::Regex;
+use ropey::{Rope, RopeSlice};
+use unicode_segmentation::UnicodeSegmentation;
 
-#[derive(Clone, Copy, PartialEq)]
+use crate::save::{self, SaveOptions};
+
+// Locations are ordered the way they appear in the buffer.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Location {
     pub line: usize,
     pub col: usize,
@@ -15,16 +23,115 @@ impl Location {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+// Decides which class chars belong to for word-wise motion. Words are runs of
+// chars of the same class, so `foo.bar` is made of three words, and only one
+// if '.' is made a word char.
+#[derive(Clone)]
+pub struct WordClasses {
+    // Chars other than letters and digits that are part of words.
+    pub word_chars: String,
+}
+
+impl Default for WordClasses {
+    fn default() -> Self {
+        Self {
+            word_chars: String::from("_"),
+        }
+    }
+}
+
+impl WordClasses {
+    pub fn class_of(&self, c: char) -> CharClass {
+        if c.is_whitespace() {
+            CharClass::Whitespace
+        } else if c.is_alphanumeric() || self.word_chars.contains(c) {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
+}
+
+// A regex match, along with the text it is to be replaced with. Like plain
+// search matches, regex matches never span multiple lines.
+pub struct RegexMatch {
+    pub location: Location,
+    pub text: String,
+    pub replacement: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+        }
+    }
+
+    // The style of a file is the style of its first line ending. Files without
+    // any line endings get the default one.
+    fn detect(contents: &str) -> Self {
+        match contents.find('\n') {
+            Some(index) if contents[..index].ends_with('\r') => LineEnding::CrLf,
+            _ => LineEnding::Lf,
+        }
+    }
+}
+
+// The text is kept in a rope, with lines separated by a single '\n' and no
+// trailing newline, so that the rope's line count matches the number of lines
+// shown to the user. Columns are char indices within a line. The editor only
+// ever places them on grapheme cluster boundaries, see the grapheme methods.
+//
+// The line ending style and whether the file ends with a line ending are kept
+// on the side and only applied when the buffer is written out.
 pub struct Buffer {
     file_path: Option<String>,
-    lines: Vec<String>,
+    text: Rope,
+    line_ending: LineEnding,
+    final_newline: bool,
+    // Whether the file had both kinds of line endings when it was loaded.
+    // Saving converts all of them to `line_ending`.
+    mixed_line_endings: bool,
+    // Whether the buffer has changed since it was last loaded or saved.
+    modified: bool,
 }
 
 impl Default for Buffer {
     fn default() -> Self {
         Self {
             file_path: None,
-            lines: vec![String::new()],
+            text: Rope::new(),
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            mixed_line_endings: false,
+            modified: false,
         }
     }
 }
@@ -35,60 +142,373 @@ impl Buffer {
     }
 
     pub fn from_file(file_path: &str) -> Result<Self> {
-        let file = File::open(file_path)?;
-        let reader = BufReader::new(file);
+        let mut contents = fs::read_to_string(file_path)?;
+
+        let line_ending = LineEnding::detect(&contents);
+        // Files with mixed line endings get normalized to the detected style.
+        let crlf_count = contents.matches("\r\n").count();
+        let mixed_line_endings = crlf_count > 0 && crlf_count < contents.matches('\n').count();
+        if crlf_count > 0 {
+            contents = contents.replace("\r\n", "\n");
+        }
+        // An empty file has no final newline, while a file that is just a line
+        // ending is a single empty line that has one.
+        let final_newline = contents.ends_with('\n');
+        if final_newline {
+            contents.pop();
+        }
 
-        let lines = reader.lines().collect::<Result<_, _>>()?;
+        let text = Rope::from_str(&contents);
         let file_path = Some(String::from(file_path));
-        let buffer = Self { file_path, lines };
+        let buffer = Self {
+            file_path,
+            text,
+            line_ending,
+            final_newline,
+            mixed_line_endings,
+            modified: false,
+        };
 
         Ok(buffer)
     }
 
-    pub fn save(&mut self) -> Result<()> {
+    pub fn save(&mut self, options: &SaveOptions) -> Result<()> {
         match self.file_path.clone() {
             None => bail!("No file path associated with the buffer"),
-            Some(path) => self.save_as(&path),
+            Some(path) => self.save_as(&path, options),
         }
     }
 
-    pub fn save_as(&mut self, file_path: &str) -> Result<()> {
-        fs::write(file_path, self.lines.join("\n"))?;
+    pub fn save_as(&mut self, file_path: &str, options: &SaveOptions) -> Result<()> {
+        save::write_atomically(Path::new(file_path), options, |writer| {
+            self.write_to(writer)
+        })?;
+
         self.file_path = Some(String::from(file_path));
+        self.mixed_line_endings = false;
+        self.modified = false;
         Ok(())
     }
 
+    fn write_to(&self, writer: &mut dyn Write) -> Result<()> {
+        let line_ending = self.line_ending.as_str();
+        for (line_number, line) in self.text.lines().enumerate() {
+            if line_number > 0 {
+                writer.write_all(line_ending.as_bytes())?;
+            }
+            for chunk in line.chunks() {
+                writer.write_all(chunk.trim_end_matches('\n').as_bytes())?;
+            }
+        }
+        if self.final_newline {
+            writer.write_all(line_ending.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if line_ending != self.line_ending {
+            self.line_ending = line_ending;
+            self.modified = true;
+        }
+    }
+
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
     pub fn file_path(&self) -> Option<&String> {
         self.file_path.as_ref()
     }
 
-    pub fn lines(&self) -> impl Iterator<Item = &String> {
-        self.lines.iter()
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        (0..self.line_count()).map(|line_number| self.get_line(line_number))
+    }
+
+    pub fn get_line(&self, line_number: usize) -> Cow<'_, str> {
+        self.line_slice(line_number).into()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    pub fn line_len(&self, line_number: usize) -> usize {
+        self.line_slice(line_number).len_chars()
+    }
+
+    pub fn next_grapheme_boundary(&self, location: Location) -> usize {
+        self.grapheme_boundaries(location.line)
+            .find(|&col| col > location.col)
+            .unwrap_or(location.col)
+    }
+
+    pub fn prev_grapheme_boundary(&self, location: Location) -> usize {
+        self.grapheme_boundaries(location.line)
+            .take_while(|&col| col < location.col)
+            .last()
+            .unwrap_or(0)
+    }
+
+    // Returns the column right after the end of the next word, skipping any
+    // whitespace in front of it. Stays within the line.
+    pub fn next_word_boundary(&self, location: Location, classes: &WordClasses) -> usize {
+        let mut graphemes = self
+            .grapheme_classes(location.line, classes)
+            .into_iter()
+            .skip_while(|&(col, _)| col < location.col)
+            .skip_while(|&(_, class)| class == CharClass::Whitespace)
+            .peekable();
+
+        let line_len = self.line_len(location.line);
+        match graphemes.peek() {
+            Some(&(_, word_class)) => graphemes
+                .find(|&(_, class)| class != word_class)
+                .map_or(line_len, |(col, _)| col),
+            None => line_len,
+        }
+    }
+
+    // Returns the column at the start of the previous word, skipping any
+    // whitespace behind it. Stays within the line.
+    pub fn prev_word_boundary(&self, location: Location, classes: &WordClasses) -> usize {
+        let mut graphemes = self
+            .grapheme_classes(location.line, classes)
+            .into_iter()
+            .rev()
+            .skip_while(|&(col, _)| col >= location.col)
+            .skip_while(|&(_, class)| class == CharClass::Whitespace)
+            .peekable();
+
+        match graphemes.peek() {
+            Some(&(_, word_class)) => graphemes
+                .take_while(|&(_, class)| class == word_class)
+                .last()
+                .map_or(0, |(col, _)| col),
+            None => 0,
+        }
+    }
+
+    // Finds the closest occurrence of `query` in the given direction, wrapping
+    // around the end (or the start) of the buffer. A forward search accepts a
+    // match at `from` itself, a backward one doesn't, so that searching back
+    // and forth between two matches alternates between them. Matches never span
+    // multiple lines.
+    pub fn find(
+        &self,
+        query: &str,
+        from: Location,
+        direction: SearchDirection,
+    ) -> Option<Location> {
+        if query.is_empty() {
+            return None;
+        }
+
+        // The starting line is visited twice: first for the part of it in the
+        // search direction, and at the very end for the rest of it.
+        let line_count = self.line_count();
+        for step in 0..=line_count {
+            let line_number = match direction {
+                SearchDirection::Forward => (from.line + step) % line_count,
+                SearchDirection::Backward => {
+                    (from.line + line_count - step % line_count) % line_count
+                }
+            };
+            let is_ahead = |col: usize| match step {
+                0 => (col >= from.col) == (direction == SearchDirection::Forward),
+                _ if step == line_count => {
+                    (col < from.col) == (direction == SearchDirection::Forward)
+                }
+                _ => true,
+            };
+
+            let mut matches = self.find_in_line(line_number, query).into_iter();
+            let found = match direction {
+                SearchDirection::Forward => matches.find(|&col| is_ahead(col)),
+                SearchDirection::Backward => matches.rev().find(|&col| is_ahead(col)),
+            };
+            if let Some(col) = found {
+                return Some(Location::new(line_number, col));
+            }
+        }
+
+        None
+    }
+
+    fn find_in_line(&self, line_number: usize, query: &str) -> Vec<usize> {
+        let line = self.get_line(line_number);
+
+        let mut matches = Vec::new();
+        let (mut byte, mut col) = (0, 0);
+        for (match_byte, _) in line.match_indices(query) {
+            col += line[byte..match_byte].chars().count();
+            byte = match_byte;
+            matches.push(col);
+        }
+        matches
+    }
+
+    // Finds the first match of `regex` at or after `from`, without wrapping
+    // around. The replacement is `replacement` with the capture groups it refers
+    // to (`$1`, `${name}`) expanded. Anchors like `^` keep matching only at the
+    // actual start of a line, even when the search starts in the middle of it.
+    pub fn find_regex(
+        &self,
+        regex: &Regex,
+        replacement: &str,
+        from: Location,
+    ) -> Option<RegexMatch> {
+        for line_number in from.line..self.line_count() {
+            let line = self.get_line(line_number);
+            let start_col = if line_number == from.line {
+                from.col
+            } else {
+                0
+            };
+            let Some((start_byte, _)) = line
+                .char_indices()
+                .chain(Some((line.len(), ' ')))
+                .nth(start_col)
+            else {
+                continue;
+            };
+
+            if let Some(captures) = regex.captures_at(&line, start_byte) {
+                let found = captures.get(0).unwrap();
+                let mut expanded = String::new();
+                captures.expand(replacement, &mut expanded);
+
+                return Some(RegexMatch {
+                    location: Location::new(line_number, line[..found.start()].chars().count()),
+                    text: String::from(found.as_str()),
+                    replacement: expanded,
+                });
+            }
+        }
+
+        None
+    }
+
+    // The text from `start` up to `end`, with lines separated by '\n'.
+    pub fn get_text(&self, start: Location, end: Location) -> String {
+        String::from(
+            self.text
+                .slice(self.char_index(start)..self.char_index(end)),
+        )
     }
 
-    pub fn get_line(&self, line_number: usize) -> &String {
-        &self.lines[line_number]
+    pub fn get_char(&self, location: Location) -> char {
+        self.text.char(self.char_index(location))
     }
 
     pub fn insert_char(&mut self, location: Location, c: char) {
-        self.lines[location.line].insert(location.col, c);
+        self.text.insert_char(self.char_index(location), c);
+        self.modified = true;
+    }
+
+    pub fn remove_char(&mut self, location: Location) -> char {
+        let index = self.char_index(location);
+        let c = self.text.char(index);
+        self.text.remove(index..index + 1);
+        self.modified = true;
+        c
     }
 
-    pub fn remove_char(&mut self, location: Location) {
-        self.lines[location.line].remove(location.col);
+    // The text must not contain line breaks.
+    pub fn insert_text(&mut self, location: Location, text: &str) {
+        self.text.insert(self.char_index(location), text);
+        self.modified = true;
+    }
+
+    pub fn remove_text(&mut self, location: Location, len: usize) -> String {
+        let index = self.char_index(location);
+        let text = String::from(self.text.slice(index..index + len));
+        self.text.remove(index..index + len);
+        self.modified = true;
+        text
     }
 
     pub fn insert_line(&mut self, line_number: usize) {
-        self.lines.insert(line_number, String::new())
+        let index = self.text.line_to_char(line_number);
+        self.text.insert_char(index, '\n');
+        self.modified = true;
+    }
+
+    pub fn remove_line(&mut self, line_number: usize) -> String {
+        let line = String::from(self.get_line(line_number));
+
+        let start = self.text.line_to_char(line_number);
+        let range = if line_number + 1 < self.line_count() {
+            start..self.text.line_to_char(line_number + 1)
+        } else {
+            start.saturating_sub(1)..self.text.len_chars()
+        };
+        self.text.remove(range);
+        self.modified = true;
+
+        line
     }
 
     pub fn join_two_lines(&mut self, first_line: usize) {
-        let second_line = self.lines.remove(first_line + 1);
-        self.lines[first_line] += &second_line;
+        let newline = self.text.line_to_char(first_line + 1) - 1;
+        self.text.remove(newline..newline + 1);
+        self.modified = true;
     }
 
     pub fn split_line(&mut self, location: Location) {
-        let second_line = self.lines[location.line].split_off(location.col);
-        self.lines.insert(location.line + 1, second_line);
+        self.text.insert_char(self.char_index(location), '\n');
+        self.modified = true;
+    }
+
+    fn char_index(&self, location: Location) -> usize {
+        self.text.line_to_char(location.line) + location.col
+    }
+
+    // Yields the columns at which the grapheme clusters of the line end.
+    fn grapheme_boundaries(&self, line_number: usize) -> impl Iterator<Item = usize> {
+        let graphemes: Vec<usize> = self
+            .get_line(line_number)
+            .graphemes(true)
+            .map(|grapheme| grapheme.chars().count())
+            .collect();
+
+        graphemes.into_iter().scan(0, |col, len| {
+            *col += len;
+            Some(*col)
+        })
+    }
+
+    // Pairs the column each grapheme of the line starts at with the class of
+    // its first char.
+    fn grapheme_classes(
+        &self,
+        line_number: usize,
+        classes: &WordClasses,
+    ) -> Vec<(usize, CharClass)> {
+        let line = self.get_line(line_number);
+        let mut col = 0;
+        line.graphemes(true)
+            .map(|grapheme| {
+                let start = col;
+                col += grapheme.chars().count();
+                (start, classes.class_of(grapheme.chars().next().unwrap()))
+            })
+            .collect()
+    }
+
+    fn line_slice(&self, line_number: usize) -> RopeSlice<'_> {
+        let line = self.text.line(line_number);
+        match line.len_chars() {
+            len if len > 0 && line.char(len - 1) == '\n' => line.slice(..len - 1),
+            _ => line,
+        }
     }
 }
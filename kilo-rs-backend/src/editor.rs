@@ -1,44 +1,137 @@
 use std::cmp;
-use std::iter::{once, repeat};
+use std::iter::{once, repeat, repeat_n};
+use std::ops::RangeInclusive;
 
-use crate::core::{Buffer, Location};
-use crate::view::{rendering::RenderedBuffer, ViewGeometry};
+use crate::core::{Buffer, LineEnding, Location, RegexMatch, SearchDirection, WordClasses};
+use crate::history::{Edit, History, Snapshot, TransactionKind};
+use crate::kill_ring::KillRing;
+use crate::save::SaveOptions;
+use crate::view::highlighting::{Highlighter, TokenClass};
+use crate::view::rendering::{RenderedBuffer, Span, StyledLine, WrappedRow, DEFAULT_TAB_WIDTH};
+use crate::view::{LineNumbers, Scrolloff, ViewGeometry, Wrap};
 
 use anyhow::Result;
+use regex::Regex;
 
 pub struct Editor {
     buffer: Buffer,
-    rendered_buffer: RenderedBuffer,
     cursor: Location,
+    // The render column of the cursor (kilo's rx), as opposed to its buffer
+    // column in `cursor` (kilo's cx). It is kept as is when moving vertically,
+    // so that the cursor returns to the same screen column after passing
+    // through shorter lines or lines with tabs.
+    cursor_rx: usize,
+    // The width of the whole text area. The view gets what the gutter leaves
+    // of it.
+    width: usize,
     view: ViewGeometry,
+    scrolloff: Scrolloff,
+    wrap: Wrap,
+    tab_width: usize,
+    line_numbers: LineNumbers,
+    auto_indent: bool,
+    // Indent with spaces, as many as a tab is wide, instead of tabs.
+    expand_tab: bool,
+    // The other end of the selection, the cursor being the end that moves.
+    selection_anchor: Option<Location>,
+    history: History,
+    kill_ring: KillRing,
+    // The kill ring entry the last paste inserted, and where it left the
+    // cursor. Pasting an older entry replaces that paste if nothing else has
+    // happened since.
+    last_paste: Option<(usize, Location)>,
+    highlighter: Highlighter,
+    save_options: SaveOptions,
+    word_classes: WordClasses,
+    // Where the cursor and the view were when the current search started, so
+    // that they can be put back if it gets cancelled.
+    search_origin: Option<Snapshot>,
+    replace_session: Option<ReplaceSession>,
+}
+
+// A find/replace going through the matches of a regex one at a time, from the
+// top of the buffer to its bottom. All of its replacements make up a single
+// transaction. The session spans many key presses, so rather than keeping a
+// transaction open all that time, the edits are collected here and only go
+// into the history once the session ends.
+struct ReplaceSession {
+    regex: Regex,
+    replacement: String,
+    // The match waiting for confirmation, if any are left.
+    current: Option<RegexMatch>,
+    count: usize,
+    edits: Vec<Edit>,
+    before: Snapshot,
 }
 
 impl Editor {
     pub fn new(width: usize, height: usize) -> Self {
-        let buffer = Buffer::new();
-        let rendered_buffer = RenderedBuffer::from(&buffer);
-
         Self {
-            buffer,
-            rendered_buffer,
+            buffer: Buffer::new(),
             cursor: Location::new(0, 0),
-            view: ViewGeometry::new(0, 0, width, height),
+            cursor_rx: 0,
+            width,
+            view: ViewGeometry::new(0, 0, width.max(1), height.max(1)),
+            scrolloff: Scrolloff::default(),
+            wrap: Wrap::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            line_numbers: LineNumbers::default(),
+            auto_indent: true,
+            expand_tab: false,
+            selection_anchor: None,
+            history: History::new(),
+            kill_ring: KillRing::new(),
+            last_paste: None,
+            highlighter: Highlighter::for_path(None),
+            save_options: SaveOptions::default(),
+            word_classes: WordClasses::default(),
+            search_origin: None,
+            replace_session: None,
         }
     }
 
+    // The cursor's position in the text area, which includes the gutter.
     pub fn get_view_cursor(&self) -> Location {
+        let gutter_width = self.get_gutter_width();
+        if self.wrap != Wrap::Off {
+            let (index, row) = self.cursor_wrapped_row();
+            let rows_above: usize = (self.view.line..self.cursor.line)
+                .map(|line| self.wrapped_rows(line).len())
+                .sum();
+            return Location::new(
+                (rows_above + index).saturating_sub(self.view.top_row),
+                gutter_width + self.rendered_buffer().cx_to_rx(self.cursor) - row.start_rx,
+            );
+        }
+
         Location::new(
             self.cursor.line - self.view.line,
-            self.cursor.col - self.view.col,
+            gutter_width + self.rendered_buffer().cx_to_rx(self.cursor) - self.view.col,
         )
     }
 
+    // Wide enough for the number of the last line, and a space after it. The
+    // gutter is left out when it would take up the whole width.
+    pub fn get_gutter_width(&self) -> usize {
+        let gutter_width = match self.line_numbers {
+            LineNumbers::Off => 0,
+            LineNumbers::Absolute | LineNumbers::Relative => {
+                self.get_buffer_line_count().to_string().len() + 1
+            }
+        };
+        if gutter_width < self.width {
+            gutter_width
+        } else {
+            0
+        }
+    }
+
     pub fn get_buffer_cursor(&self) -> Location {
         self.cursor
     }
 
     pub fn get_buffer_line_count(&self) -> usize {
-        self.rendered_buffer.line_count()
+        self.rendered_buffer().line_count()
     }
 
     pub fn get_view_width(&self) -> usize {
@@ -49,97 +142,860 @@ impl Editor {
         self.view.height
     }
 
-    pub fn get_view_contents(&self) -> impl Iterator<Item = String> {
+    // The size of the whole text area, the gutter included. The view gets
+    // scrolled for the cursor to stay in it. It keeps at least one row even
+    // when the terminal has none to spare, since the cursor has to be on one.
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.view.height = height.max(1);
+        self.update_view_width();
+        self.scroll_into_view();
+    }
+
+    pub fn get_view_contents(&self) -> impl Iterator<Item = StyledLine> {
         let ViewGeometry {
             line,
             col,
+            top_row,
             width,
             height,
         } = self.view;
-        let filler = once("~").chain(repeat(" ")).take(width).collect();
-        self.rendered_buffer
-            .get_view(line, col, width, height)
+        let filler = Span {
+            text: once("~").chain(repeat(" ")).take(self.width).collect(),
+            class: TokenClass::Normal,
+            selected: false,
+        };
+        let rendered_buffer = self.rendered_buffer();
+        let view = match self.wrap {
+            Wrap::Off => rendered_buffer.get_view(
+                &self.highlighter,
+                self.get_selection(),
+                line,
+                col,
+                width,
+                height,
+            ),
+            wrap => rendered_buffer.get_wrapped_view(
+                &self.highlighter,
+                self.get_selection(),
+                line,
+                top_row,
+                width,
+                height,
+                wrap == Wrap::Words,
+            ),
+        };
+
+        let gutter_width = self.get_gutter_width();
+        let rows: Vec<StyledLine> = view
             .into_iter()
-            .chain(repeat(filler))
-            .take(height)
+            .zip(self.row_lines())
+            .map(|(mut row, line)| {
+                if gutter_width > 0 {
+                    row.insert(0, self.gutter_span(line, gutter_width));
+                }
+                row
+            })
+            .collect();
+        rows.into_iter().chain(repeat(vec![filler])).take(height)
+    }
+
+    // The line each row of the view shows the start of. Rows that continue a
+    // wrapped line don't get one.
+    fn row_lines(&self) -> Vec<Option<usize>> {
+        let lines = self.view.line..self.get_buffer_line_count();
+        if self.wrap == Wrap::Off {
+            return lines.take(self.view.height).map(Some).collect();
+        }
+
+        lines
+            .flat_map(|line| {
+                let continuations = self.wrapped_rows(line).len() - 1;
+                once(Some(line)).chain(repeat_n(None, continuations))
+            })
+            .skip(self.view.top_row)
+            .take(self.view.height)
+            .collect()
+    }
+
+    // In relative mode, the cursor line keeps its absolute number, and the
+    // other lines are numbered by their distance from it.
+    fn gutter_span(&self, line: Option<usize>, gutter_width: usize) -> Span {
+        let digits = gutter_width - 1;
+        let (text, class) = match line {
+            Some(line) if line == self.cursor.line => (
+                format!("{:>digits$} ", line + 1),
+                TokenClass::CurrentLineNumber,
+            ),
+            Some(line) => {
+                let number = match self.line_numbers {
+                    LineNumbers::Relative => line.abs_diff(self.cursor.line),
+                    _ => line + 1,
+                };
+                (format!("{number:>digits$} "), TokenClass::LineNumber)
+            }
+            None => (" ".repeat(gutter_width), TokenClass::LineNumber),
+        };
+
+        Span {
+            text,
+            class,
+            selected: false,
+        }
     }
 
     pub fn get_file_name(&self) -> Option<&String> {
         self.buffer.file_path()
     }
 
+    pub fn is_modified(&self) -> bool {
+        self.buffer.is_modified()
+    }
+
+    pub fn get_line_ending(&self) -> LineEnding {
+        self.buffer.line_ending()
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.buffer.set_line_ending(line_ending)
+    }
+
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.buffer.has_mixed_line_endings()
+    }
+
     pub fn open_file(&mut self, file_path: &str) -> Result<()> {
         self.buffer = Buffer::from_file(file_path)?;
-        self.rendered_buffer = RenderedBuffer::from(&self.buffer);
         self.cursor = Location::new(0, 0);
+        self.cursor_rx = 0;
+        self.view.line = 0;
+        self.view.col = 0;
+        self.view.top_row = 0;
+        self.selection_anchor = None;
+        self.history = History::new();
+        self.highlighter = Highlighter::for_path(Some(file_path));
+        self.search_origin = None;
+        self.replace_session = None;
+        self.update_view_width();
 
         Ok(())
     }
 
     pub fn save_file(&mut self) -> Result<()> {
-        self.buffer.save()
+        self.buffer.save(&self.save_options)
     }
 
+    // Saving under a different extension can change the language.
     pub fn save_file_as(&mut self, file_path: &str) -> Result<()> {
-        self.buffer.save_as(file_path)
+        self.buffer.save_as(file_path, &self.save_options)?;
+        self.highlighter = Highlighter::for_path(Some(file_path));
+        Ok(())
+    }
+
+    pub fn set_save_options(&mut self, save_options: SaveOptions) {
+        self.save_options = save_options;
+    }
+
+    pub fn set_scrolloff(&mut self, scrolloff: Scrolloff) {
+        self.scrolloff = scrolloff;
+        self.scroll_into_view();
+    }
+
+    pub fn get_wrap(&self) -> Wrap {
+        self.wrap
+    }
+
+    pub fn set_wrap(&mut self, wrap: Wrap) {
+        self.wrap = wrap;
+        self.view.col = 0;
+        self.view.top_row = 0;
+        self.scroll_into_view();
+    }
+
+    // Render columns depend on the tab width, so the cursor's gets recomputed.
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+        self.update_cursor_rx();
+    }
+
+    pub fn get_line_numbers(&self) -> LineNumbers {
+        self.line_numbers
+    }
+
+    pub fn set_line_numbers(&mut self, line_numbers: LineNumbers) {
+        self.line_numbers = line_numbers;
+        self.update_view_width();
+        self.scroll_into_view();
+    }
+
+    pub fn get_auto_indent(&self) -> bool {
+        self.auto_indent
+    }
+
+    pub fn set_auto_indent(&mut self, auto_indent: bool) {
+        self.auto_indent = auto_indent;
+    }
+
+    pub fn set_expand_tab(&mut self, expand_tab: bool) {
+        self.expand_tab = expand_tab;
+    }
+
+    pub fn set_word_classes(&mut self, word_classes: WordClasses) {
+        self.word_classes = word_classes;
+    }
+
+    // Returns the start and the end of the selection, in buffer order. Empty
+    // selections don't count.
+    pub fn get_selection(&self) -> Option<(Location, Location)> {
+        let anchor = self.selection_anchor?;
+        match anchor.cmp(&self.cursor) {
+            cmp::Ordering::Less => Some((anchor, self.cursor)),
+            cmp::Ordering::Greater => Some((self.cursor, anchor)),
+            cmp::Ordering::Equal => None,
+        }
+    }
+
+    // Moves the cursor with `motion` while keeping the other end of the
+    // selection in place. Without a selection, one gets started at the cursor.
+    pub fn extend_selection(&mut self, motion: impl FnOnce(&mut Self)) {
+        self.selection_anchor.get_or_insert(self.cursor);
+        motion(self);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection_anchor = None;
+    }
+
+    // Both of these return the text that went into the kill ring.
+    pub fn copy_selection(&mut self) -> Option<String> {
+        let (start, end) = self.get_selection()?;
+        let text = self.buffer.get_text(start, end);
+        self.kill_ring.push(text.clone());
+        Some(text)
+    }
+
+    pub fn cut_selection(&mut self) -> Option<String> {
+        let text = self.copy_selection();
+        self.delete_selection();
+        text
+    }
+
+    // Replaces the selection, if there is one.
+    pub fn paste(&mut self) {
+        self.paste_entry(0);
+    }
+
+    // Right after a paste, replaces the pasted text with the entry before it
+    // in the kill ring, cycling back to the most recent one at the end.
+    pub fn paste_older(&mut self) {
+        match self.last_paste {
+            Some((index, cursor)) if cursor == self.cursor => {
+                self.undo();
+                self.paste_entry((index + 1) % self.kill_ring.len());
+            }
+            _ => self.paste(),
+        }
+    }
+
+    fn paste_entry(&mut self, index: usize) {
+        let Some(text) = self.kill_ring.get(index).map(String::from) else {
+            return;
+        };
+
+        self.delete_selection();
+        self.begin_transaction(TransactionKind::Other);
+        self.insert_text(&text);
+        self.update_cursor_rx();
+        self.end_transaction();
+
+        self.last_paste = Some((index, self.cursor));
+    }
+
+    // Returns whether there was anything to delete.
+    fn delete_selection(&mut self) -> bool {
+        let selection = self.get_selection();
+        self.selection_anchor = None;
+
+        match selection {
+            Some((start, end)) => {
+                self.begin_transaction(TransactionKind::Other);
+                self.delete_range(start, end);
+                self.update_cursor_rx();
+                self.end_transaction();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Edits only ever change a single line, so text spanning multiple lines
+    // gets removed line by line, joining each emptied line break away.
+    fn delete_range(&mut self, start: Location, end: Location) {
+        if start.line == end.line {
+            self.remove_text(start, end);
+        } else {
+            let eol = Location::new(start.line, self.buffer.line_len(start.line));
+            self.remove_text(start, eol);
+
+            let next_line = Location::new(start.line + 1, 0);
+            for line in start.line + 1..=end.line {
+                let len = match line == end.line {
+                    true => end.col,
+                    false => self.buffer.line_len(next_line.line),
+                };
+                self.remove_text(next_line, Location::new(next_line.line, len));
+                self.edit(Edit::JoinLines(start));
+            }
+        }
+
+        self.cursor = start;
+    }
+
+    // Both locations have to be on the same line.
+    fn remove_text(&mut self, start: Location, end: Location) {
+        if start.col < end.col {
+            let text = self.buffer.get_text(start, end);
+            self.edit(Edit::RemoveText(start, text));
+        }
+    }
+
+    // Inserts text at the cursor, leaving the cursor at its end. Line breaks
+    // in it split the line the cursor is on.
+    fn insert_text(&mut self, text: &str) {
+        for (index, part) in text.split('\n').enumerate() {
+            if index > 0 {
+                self.edit(Edit::SplitLine(self.cursor));
+                self.cursor = Location::new(self.cursor.line + 1, 0);
+            }
+            if !part.is_empty() {
+                self.edit(Edit::InsertText(self.cursor, String::from(part)));
+                self.cursor.col += part.chars().count();
+            }
+        }
     }
 
     pub fn remove_char_in_front(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+
         if self.is_cursor_at_eol_col() {
             if !self.is_cursor_at_buffer_bottom() {
-                self.buffer.join_two_lines(self.cursor.line);
-
-                self.rendered_buffer.remove_line(self.cursor.line + 1);
-                self.rendered_buffer
-                    .update_line(self.cursor.line, &self.buffer);
+                self.begin_transaction(TransactionKind::Other);
+                self.edit(Edit::JoinLines(self.cursor));
+                self.end_transaction();
             }
         } else {
-            self.buffer.remove_char(self.cursor);
-
-            self.rendered_buffer
-                .update_line(self.cursor.line, &self.buffer)
+            self.begin_transaction(TransactionKind::Deletion);
+            self.remove_grapheme_in_front();
+            self.end_transaction();
         }
     }
 
     pub fn remove_char_behind(&mut self) {
+        if self.delete_selection() {
+            return;
+        }
+
         if self.is_cursor_at_line_start() {
             if !self.is_cursor_at_buffer_top() {
-                if self.is_cursor_at_view_top() {
-                    self.move_view_up_unchecked()
-                }
+                self.begin_transaction(TransactionKind::Other);
 
                 self.cursor.line -= 1;
-                self.cursor.col = self.rendered_buffer.eol_col(self.cursor.line);
-
-                self.buffer.join_two_lines(self.cursor.line);
+                self.move_cursor_to_eol_col();
+                self.update_cursor_rx();
 
-                self.rendered_buffer.remove_line(self.cursor.line + 1);
-                self.rendered_buffer
-                    .update_line(self.cursor.line, &self.buffer);
+                self.edit(Edit::JoinLines(self.cursor));
+                self.end_transaction();
             }
         } else {
+            self.begin_transaction(TransactionKind::Deletion);
             self.move_cursor_left_unchecked();
+            self.update_cursor_rx();
+
+            self.remove_grapheme_in_front();
+            self.end_transaction();
+        }
+    }
+
+    // Like the char-wise deletions, deleting at the start (or the end) of a
+    // line joins it with the line before (or after) it.
+    pub fn remove_word_behind(&mut self) {
+        if self.is_cursor_at_line_start() || self.get_selection().is_some() {
+            self.remove_char_behind();
+            return;
+        }
+
+        let start_col = self
+            .buffer
+            .prev_word_boundary(self.cursor, &self.word_classes);
+        self.begin_transaction(TransactionKind::Deletion);
+        self.delete_range(Location::new(self.cursor.line, start_col), self.cursor);
+        self.update_cursor_rx();
+        self.end_transaction();
+    }
 
-            self.buffer.remove_char(self.cursor);
-            self.rendered_buffer
-                .update_line(self.cursor.line, &self.buffer)
+    pub fn remove_word_in_front(&mut self) {
+        if self.is_cursor_at_eol_col() || self.get_selection().is_some() {
+            self.remove_char_in_front();
+            return;
         }
+
+        let end_col = self
+            .buffer
+            .next_word_boundary(self.cursor, &self.word_classes);
+        self.begin_transaction(TransactionKind::Deletion);
+        self.delete_range(self.cursor, Location::new(self.cursor.line, end_col));
+        self.end_transaction();
     }
 
     pub fn insert_char(&mut self, c: char) {
-        self.buffer.insert_char(self.cursor, c);
+        self.delete_selection();
+        self.begin_transaction(TransactionKind::Typing);
+        if let Some(opening) = self.highlighter.opening_bracket(c) {
+            if self.auto_indent {
+                self.align_closing_bracket(opening, c);
+            }
+        }
+        self.edit(Edit::InsertChar(self.cursor, c));
         self.move_cursor_right_unchecked();
+        self.update_cursor_rx();
+        self.end_transaction();
+    }
+
+    // A grapheme can be made of several chars, all of which get removed, so
+    // that e.g. a letter and its combining accent disappear together.
+    fn remove_grapheme_in_front(&mut self) {
+        let end = self.buffer.next_grapheme_boundary(self.cursor);
+        for _ in self.cursor.col..end {
+            self.edit(Edit::RemoveChar(
+                self.cursor,
+                self.buffer.get_char(self.cursor),
+            ));
+        }
+    }
+
+    pub fn start_search(&mut self) {
+        self.search_origin = Some(self.snapshot());
+    }
+
+    // Jumps to the first match at or after the position the search started
+    // from. Refining the query thus stays on the same match while it still
+    // matches, and goes back to the starting position when nothing does.
+    pub fn search(&mut self, query: &str) {
+        if let Some(origin) = self.search_origin {
+            self.restore_snapshot(origin);
+            if let Some(location) = self
+                .buffer
+                .find(query, origin.cursor, SearchDirection::Forward)
+            {
+                self.move_cursor_to_match(location);
+            }
+        }
+    }
+
+    pub fn search_next(&mut self, query: &str, direction: SearchDirection) {
+        let from = match direction {
+            SearchDirection::Forward => Location::new(self.cursor.line, self.cursor.col + 1),
+            SearchDirection::Backward => self.cursor,
+        };
+        if let Some(location) = self.buffer.find(query, from, direction) {
+            self.move_cursor_to_match(location);
+        }
+    }
+
+    pub fn end_search(&mut self) {
+        self.search_origin = None;
+    }
+
+    pub fn cancel_search(&mut self) {
+        if let Some(origin) = self.search_origin.take() {
+            self.restore_snapshot(origin);
+        }
+    }
+
+    // Matches outside of the view get centered vertically in it.
+    pub fn move_cursor_to_match(&mut self, location: Location) {
+        self.selection_anchor = None;
+        self.cursor = location;
+        if location.line < self.view.line || location.line > self.view.last_line() {
+            self.center_view_on_cursor_line();
+        }
+        self.update_cursor_rx();
+    }
+
+    // Places the cursor anywhere in the buffer, centering the view on it.
+    // Locations past the end of the buffer or of a line get clamped to it, and
+    // ones inside of a grapheme get moved to its start.
+    pub fn move_cursor_to(&mut self, location: Location) {
+        let line = location.line.min(self.rendered_buffer().last_line());
+        let col = location.col.min(self.buffer.line_len(line));
+        let col = self
+            .buffer
+            .prev_grapheme_boundary(Location::new(line, col + 1));
+
+        self.selection_anchor = None;
+        self.cursor = Location::new(line, col);
+        self.center_view_on_cursor_line();
+        self.update_cursor_rx();
+    }
+
+    // Places the cursor at a location in the view, in the same terms as
+    // `get_view_cursor`: the gutter is part of the columns, and with wrapping
+    // on, the rows are screen rows. Locations in the gutter go to the start
+    // of the line, ones past the end of a line or of the buffer to its end.
+    pub fn move_cursor_to_view_location(&mut self, location: Location) {
+        let (line, index) = self.line_at_view_row(location.line);
+        let screen_col = location.col.saturating_sub(self.get_gutter_width());
+        if self.wrap != Wrap::Off {
+            self.place_cursor_in_wrapped_row(line, index, screen_col);
+        } else {
+            self.cursor.line = line;
+            self.cursor.col = self
+                .rendered_buffer()
+                .rx_to_cx(line, self.view.col + screen_col);
+            self.update_cursor_rx();
+        }
+    }
+
+    // Scrolls the view by `rows` screen rows, down for positive ones, until
+    // either end of the buffer is reached. The cursor stays where it is,
+    // unless it would leave the view (or the scrolloff margins) that way. It
+    // then moves to the closest row that is still in it, which drops the
+    // selection rather than extending it.
+    pub fn scroll_view(&mut self, rows: isize) {
+        let cursor_row = self.get_view_cursor().line as isize;
+
+        let mut scrolled = 0;
+        while scrolled < rows && self.scroll_view_down_one() {
+            scrolled += 1;
+        }
+        while scrolled > rows && self.scroll_view_up_one() {
+            scrolled -= 1;
+        }
+
+        let height = self.view.height;
+        let margin = self.scrolloff.vertical.min(height.saturating_sub(1) / 2);
+        let top = if (self.view.line, self.view.top_row) == (0, 0) {
+            0
+        } else {
+            margin
+        };
+        let bottom = if self.is_buffer_end_in_view() {
+            height.saturating_sub(1)
+        } else {
+            height.saturating_sub(1 + margin)
+        };
+
+        let row = cursor_row - scrolled;
+        let new_row = row.clamp(top as isize, bottom as isize);
+        if new_row == row {
+            return;
+        }
+        self.selection_anchor = None;
+        self.move_cursor_to_view_row(new_row as usize);
+    }
+
+    fn scroll_view_up_one(&mut self) -> bool {
+        if self.wrap == Wrap::Off || self.view.top_row == 0 {
+            if self.view.line == 0 {
+                return false;
+            }
+            self.view.line -= 1;
+            if self.wrap != Wrap::Off {
+                self.view.top_row = self.wrapped_rows(self.view.line).len() - 1;
+            }
+        } else {
+            self.view.top_row -= 1;
+        }
+        true
+    }
+
+    fn scroll_view_down_one(&mut self) -> bool {
+        if self.is_buffer_end_in_view() {
+            return false;
+        }
+        if self.wrap == Wrap::Off {
+            self.view.line += 1;
+        } else if self.view.top_row + 1 < self.wrapped_rows(self.view.line).len() {
+            self.view.top_row += 1;
+        } else {
+            self.view.line += 1;
+            self.view.top_row = 0;
+        }
+        true
+    }
+
+    fn is_buffer_end_in_view(&self) -> bool {
+        self.rows_from_view_top(self.view.height + 1) <= self.view.height
+    }
+
+    // The number of screen rows from the top of the view to the end of the
+    // buffer, counting no further than `limit`.
+    fn rows_from_view_top(&self, limit: usize) -> usize {
+        let line_count = self.rendered_buffer().line_count();
+        if self.wrap == Wrap::Off {
+            return line_count.saturating_sub(self.view.line).min(limit);
+        }
+
+        let mut rows = 0;
+        for line in self.view.line..line_count {
+            rows += self.wrapped_rows(line).len();
+            if rows.saturating_sub(self.view.top_row) >= limit {
+                return limit;
+            }
+        }
+        rows.saturating_sub(self.view.top_row)
+    }
+
+    // Moves the cursor to a row of the view, keeping its screen column (or
+    // the one it sticks to) like moving vertically does.
+    fn move_cursor_to_view_row(&mut self, row: usize) {
+        let (line, index) = self.line_at_view_row(row);
+        if self.wrap != Wrap::Off {
+            self.move_cursor_to_wrapped_row(line, index);
+        } else {
+            self.cursor.line = line;
+            self.adjust_cursor_cx_to_rx();
+        }
+    }
+
+    // Returns the line shown in the `row`th screen row of the view, along
+    // with the index of its wrapped row that is shown there. Rows past the
+    // end of the buffer give the last one of it.
+    fn line_at_view_row(&self, row: usize) -> (usize, usize) {
+        let last_line = self.rendered_buffer().last_line();
+        if self.wrap == Wrap::Off {
+            return (cmp::min(self.view.line + row, last_line), 0);
+        }
+
+        let mut remaining = self.view.top_row + row;
+        for line in self.view.line..=last_line {
+            let rows = self.wrapped_rows(line).len();
+            if remaining < rows {
+                return (line, remaining);
+            }
+            remaining -= rows;
+        }
+        (last_line, self.wrapped_rows(last_line).len() - 1)
+    }
+
+    // With wrapping on, it's the cursor's screen row that gets centered.
+    fn center_view_on_cursor_line(&mut self) {
+        if self.wrap != Wrap::Off {
+            let (index, _) = self.cursor_wrapped_row();
+            self.view.line = self.cursor.line;
+            self.view.top_row = index;
+            for _ in 0..self.view.height / 2 {
+                if !self.scroll_view_up_one() {
+                    break;
+                }
+            }
+            // Rather than leaving rows empty at the bottom of the buffer,
+            // more of the rows above it get shown.
+            while self.rows_from_view_top(self.view.height) < self.view.height
+                && self.scroll_view_up_one()
+            {}
+            return;
+        }
+
+        self.view.line = cmp::min(
+            self.cursor.line.saturating_sub(self.view.height / 2),
+            self.bottom_most_view_pos(),
+        );
+    }
+
+    // Returns whether there is a match to confirm. If there isn't, the session
+    // still has to be ended.
+    pub fn start_replace(&mut self, pattern: &str, replacement: &str) -> Result<bool> {
+        let regex = Regex::new(pattern)?;
+        self.end_replace();
+        self.selection_anchor = None;
+
+        let current = self
+            .buffer
+            .find_regex(&regex, replacement, Location::new(0, 0));
+
+        self.last_paste = None;
+        self.replace_session = Some(ReplaceSession {
+            regex,
+            replacement: String::from(replacement),
+            current: None,
+            count: 0,
+            edits: Vec::new(),
+            before: self.snapshot(),
+        });
+        Ok(self.move_to_replace_match(current))
+    }
+
+    // Both of these return whether there is another match to confirm.
+    pub fn replace_match(&mut self) -> bool {
+        match self.replace_session.as_mut().and_then(|s| s.current.take()) {
+            Some(found) => {
+                let location = found.location;
+                let replacement_len = found.replacement.chars().count();
+                let skip = if found.text.is_empty() { 1 } else { 0 };
+
+                let edits = [
+                    Edit::RemoveText(location, found.text),
+                    Edit::InsertText(location, found.replacement),
+                ];
+                for edit in &edits {
+                    self.apply_edit(edit);
+                }
+                if let Some(session) = self.replace_session.as_mut() {
+                    session.edits.extend(edits);
+                    session.count += 1;
+                }
+
+                let from = Location::new(location.line, location.col + replacement_len + skip);
+                self.find_next_replace_match(from)
+            }
+            None => false,
+        }
+    }
+
+    pub fn skip_match(&mut self) -> bool {
+        match self.replace_session.as_mut().and_then(|s| s.current.take()) {
+            // Empty matches are stepped over, so that the search moves on.
+            Some(found) => {
+                let len = found.text.chars().count().max(1);
+                let from = Location::new(found.location.line, found.location.col + len);
+                self.find_next_replace_match(from)
+            }
+            None => false,
+        }
+    }
+
+    pub fn replace_all(&mut self) {
+        while self.replace_match() {}
+    }
+
+    // Returns the number of replacements made.
+    pub fn end_replace(&mut self) -> usize {
+        match self.replace_session.take() {
+            Some(session) => {
+                self.history.begin(TransactionKind::Other, session.before);
+                for edit in session.edits {
+                    self.history.record(edit);
+                }
+                self.end_transaction();
+                session.count
+            }
+            None => 0,
+        }
+    }
+
+    fn find_next_replace_match(&mut self, from: Location) -> bool {
+        let found = self.replace_session.as_ref().and_then(|session| {
+            self.buffer
+                .find_regex(&session.regex, &session.replacement, from)
+        });
+        self.move_to_replace_match(found)
+    }
+
+    fn move_to_replace_match(&mut self, found: Option<RegexMatch>) -> bool {
+        if let Some(found) = &found {
+            self.move_cursor_to_match(found.location);
+        }
+        let has_match = found.is_some();
+        if let Some(session) = self.replace_session.as_mut() {
+            session.current = found;
+        }
+        has_match
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(transaction) = self.history.pop_undo() {
+            for edit in transaction.edits.iter().rev() {
+                self.apply_edit(&edit.inverse());
+            }
+            self.restore_snapshot(transaction.before);
+            self.history.push_redo(transaction);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(transaction) = self.history.pop_redo() {
+            for edit in transaction.edits.iter() {
+                self.apply_edit(edit);
+            }
+            self.restore_snapshot(transaction.after);
+            self.history.push_undo(transaction);
+        }
+    }
+
+    fn begin_transaction(&mut self, kind: TransactionKind) {
+        self.last_paste = None;
+        self.history.begin(kind, self.snapshot());
+    }
+
+    fn end_transaction(&mut self) {
+        self.scroll_into_view();
+        self.history.end(self.snapshot());
+    }
+
+    fn edit(&mut self, edit: Edit) {
+        self.apply_edit(&edit);
+        self.history.record(edit);
+    }
+
+    fn apply_edit(&mut self, edit: &Edit) {
+        self.highlighter.invalidate_from(edit.line());
+        match *edit {
+            Edit::InsertChar(location, c) => self.buffer.insert_char(location, c),
+            Edit::RemoveChar(location, _) => {
+                self.buffer.remove_char(location);
+            }
+            Edit::InsertText(location, ref text) => self.buffer.insert_text(location, text),
+            Edit::RemoveText(location, ref text) => {
+                self.buffer.remove_text(location, text.chars().count());
+            }
+            Edit::InsertLine(line) => self.buffer.insert_line(line),
+            Edit::RemoveLine(line) => {
+                self.buffer.remove_line(line);
+            }
+            Edit::SplitLine(location) => self.buffer.split_line(location),
+            Edit::JoinLines(location) => self.buffer.join_two_lines(location.line),
+        }
+        self.update_view_width();
+    }
+
+    // The gutter grows with the line count, taking width away from the view,
+    // so this has to follow every change to the width, the line numbers
+    // setting or the line count. Like the height, the width never drops below
+    // one column, which the cursor needs to be in.
+    fn update_view_width(&mut self) {
+        self.view.width = self.width.saturating_sub(self.get_gutter_width()).max(1);
+    }
+
+    fn rendered_buffer(&self) -> RenderedBuffer<'_> {
+        RenderedBuffer::new(&self.buffer, self.tab_width)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            cursor: self.cursor,
+            view: Location::new(self.view.line, self.view.col),
+            view_top_row: self.view.top_row,
+        }
+    }
 
-        self.rendered_buffer
-            .update_line(self.cursor.line, &self.buffer);
+    fn restore_snapshot(&mut self, snapshot: Snapshot) {
+        self.selection_anchor = None;
+        self.cursor = snapshot.cursor;
+        self.view.line = snapshot.view.line;
+        self.view.col = snapshot.view.col;
+        self.view.top_row = snapshot.view_top_row;
+        self.update_cursor_rx();
     }
 
     fn move_cursor_left_unchecked(&mut self) {
-        self.cursor.col -= 1
+        self.cursor.col = self.buffer.prev_grapheme_boundary(self.cursor)
     }
 
     fn move_cursor_right_unchecked(&mut self) {
-        self.cursor.col += 1
+        self.cursor.col = self.buffer.next_grapheme_boundary(self.cursor)
     }
 
     fn move_cursor_down_unchecked(&mut self) {
@@ -147,101 +1003,320 @@ impl Editor {
     }
 
     pub fn move_cursor_up(&mut self) {
-        if self.is_cursor_at_buffer_top() {
+        if self.wrap != Wrap::Off {
+            let (index, _) = self.cursor_wrapped_row();
+            if index > 0 {
+                self.move_cursor_to_wrapped_row(self.cursor.line, index - 1);
+            } else if !self.is_cursor_at_buffer_top() {
+                let line = self.cursor.line - 1;
+                let last_index = self.wrapped_rows(line).len() - 1;
+                self.move_cursor_to_wrapped_row(line, last_index);
+            }
             return;
         }
 
-        if self.is_cursor_at_view_top() {
-            self.move_view_up_unchecked();
+        if self.is_cursor_at_buffer_top() {
+            return;
         }
 
         self.cursor.line -= 1;
-        self.adjust_cursor_past_eol();
+        self.adjust_cursor_cx_to_rx();
     }
 
     pub fn insert_line(&mut self) {
-        let should_move_view = self.is_cursor_at_view_bottom();
+        self.delete_selection();
+        self.begin_transaction(TransactionKind::Other);
 
+        let (indent, closing_indent) = self.new_line_indent();
         if self.is_cursor_at_line_start() {
-            self.buffer.insert_line(self.cursor.line);
-            self.rendered_buffer
-                .insert_line(self.cursor.line, &self.buffer);
+            self.edit(Edit::InsertLine(self.cursor.line));
             self.move_cursor_down_unchecked();
         } else if self.is_cursor_at_eol_col() {
-            let insert_index = self.cursor.line + 1;
-            self.buffer.insert_line(insert_index);
-            self.rendered_buffer.insert_line(insert_index, &self.buffer);
+            self.edit(Edit::InsertLine(self.cursor.line + 1));
             self.move_cursor_down_unchecked();
             self.move_cursor_to_line_start();
         } else {
-            self.buffer.split_line(self.cursor);
-            self.rendered_buffer
-                .update_line(self.cursor.line, &self.buffer);
-            self.rendered_buffer
-                .insert_line(self.cursor.line + 1, &self.buffer);
+            self.edit(Edit::SplitLine(self.cursor));
             self.move_cursor_down_unchecked();
             self.move_cursor_to_line_start();
         }
 
-        if should_move_view {
-            self.move_view_down_unchecked();
+        // Splitting a line right between a pair of brackets moves the closing
+        // one down another line, dedented, and leaves the cursor on the
+        // indented line in between.
+        self.insert_text(&indent);
+        if let Some(closing_indent) = closing_indent {
+            let cursor = self.cursor;
+            self.insert_text(&format!("\n{closing_indent}"));
+            self.cursor = cursor;
+        }
+        self.update_cursor_rx();
+
+        self.end_transaction();
+    }
+
+    // The indentation for a line inserted at the cursor: that of the line the
+    // cursor is on, one level deeper after an opening bracket. When the
+    // cursor is right between an opening and a closing bracket, the closing
+    // one's indentation is returned too.
+    fn new_line_indent(&self) -> (String, Option<String>) {
+        if !self.auto_indent || self.is_cursor_at_line_start() {
+            return (String::new(), None);
+        }
+
+        let line = self.buffer.get_line(self.cursor.line);
+        let before: String = line.chars().take(self.cursor.col).collect();
+        let indent: String = before.chars().take_while(|c| c.is_whitespace()).collect();
+
+        let last = before.trim_end().chars().last();
+        if !last.is_some_and(|c| self.highlighter.indents_after(c)) {
+            return (indent, None);
+        }
+
+        let next = line.chars().nth(self.cursor.col);
+        let is_between_brackets = next.is_some_and(|c| self.highlighter.opening_bracket(c) == last);
+        (
+            indent.clone() + &self.indent_unit(self.cursor.line),
+            is_between_brackets.then_some(indent),
+        )
+    }
+
+    // Gives a closing bracket typed at the start of a line the indentation of
+    // the line its opening bracket is on. Without a matching opening bracket,
+    // a level of indentation is removed instead.
+    fn align_closing_bracket(&mut self, opening: char, closing: char) {
+        let line = self.buffer.get_line(self.cursor.line);
+        let before: Vec<char> = line.chars().take(self.cursor.col).collect();
+        if !before.iter().all(|c| c.is_whitespace()) {
+            return;
+        }
+
+        if let Some(opening_line) = self.find_opening_bracket_line(opening, closing) {
+            let indent = self.line_indent(opening_line);
+            self.remove_text(Location::new(self.cursor.line, 0), self.cursor);
+            self.cursor.col = 0;
+            self.insert_text(&indent);
+            return;
+        }
+
+        let len = if before.last() == Some(&'\t') {
+            1
+        } else {
+            let spaces = before.iter().rev().take_while(|&&c| c == ' ').count();
+            spaces.min(self.indent_unit(self.cursor.line).chars().count())
+        };
+        let start = Location::new(self.cursor.line, self.cursor.col - len);
+        self.remove_text(start, self.cursor);
+        self.cursor = start;
+    }
+
+    // The line of the bracket that a closing bracket typed at the cursor would
+    // close. Brackets in strings and comments don't count.
+    fn find_opening_bracket_line(&self, opening: char, closing: char) -> Option<usize> {
+        let mut depth = 0;
+        for line in (0..=self.cursor.line).rev() {
+            let classes = self.highlighter.highlight_line(&self.buffer, line);
+            let chars: Vec<char> = self.buffer.get_line(line).chars().collect();
+            let end = if line == self.cursor.line {
+                self.cursor.col
+            } else {
+                chars.len()
+            };
+
+            for col in (0..end).rev() {
+                if matches!(classes[col], TokenClass::String | TokenClass::Comment) {
+                    continue;
+                }
+                if chars[col] == closing {
+                    depth += 1;
+                } else if chars[col] == opening {
+                    if depth == 0 {
+                        return Some(line);
+                    }
+                    depth -= 1;
+                }
+            }
+        }
+        None
+    }
+
+    // Indents the lines the selection touches. Without a selection, a tab (or
+    // the spaces up to the next tab stop) gets typed instead.
+    pub fn indent(&mut self) {
+        if self.get_selection().is_none() {
+            let rx = self.rendered_buffer().cx_to_rx(self.cursor);
+            let text = if self.expand_tab {
+                " ".repeat(self.tab_width - rx % self.tab_width)
+            } else {
+                "\t".into()
+            };
+
+            self.selection_anchor = None;
+            self.begin_transaction(TransactionKind::Typing);
+            self.insert_text(&text);
+            self.update_cursor_rx();
+            self.end_transaction();
+            return;
+        }
+
+        // Empty lines are left alone, they'd only get trailing whitespace.
+        let unit = self.indent_unit(self.cursor.line);
+        let len = unit.chars().count();
+        self.begin_transaction(TransactionKind::Other);
+        for line in self.indented_lines() {
+            if self.buffer.line_len(line) > 0 {
+                self.edit(Edit::InsertText(Location::new(line, 0), unit.clone()));
+                self.shift_line_locations(line, |col| if col > 0 { col + len } else { 0 });
+            }
+        }
+        self.update_cursor_rx();
+        self.end_transaction();
+    }
+
+    // Removes a level of indentation from the lines the selection touches, or
+    // from the cursor line without a selection.
+    pub fn dedent(&mut self) {
+        self.begin_transaction(TransactionKind::Other);
+        for line in self.indented_lines() {
+            let indent = self.line_indent(line);
+            let len = if indent.starts_with('\t') {
+                1
+            } else {
+                let spaces = indent.chars().take_while(|&c| c == ' ').count();
+                spaces.min(self.indent_unit(line).chars().count())
+            };
+
+            if len > 0 {
+                self.remove_text(Location::new(line, 0), Location::new(line, len));
+                self.shift_line_locations(line, |col| col.saturating_sub(len));
+            }
+        }
+        self.update_cursor_rx();
+        self.end_transaction();
+    }
+
+    // A selection that ends at the start of a line doesn't touch that line.
+    fn indented_lines(&self) -> RangeInclusive<usize> {
+        match self.get_selection() {
+            Some((start, end)) if end.line > start.line && end.col == 0 => {
+                start.line..=end.line - 1
+            }
+            Some((start, end)) => start.line..=end.line,
+            None => self.cursor.line..=self.cursor.line,
+        }
+    }
+
+    // Keeps the cursor and the selection anchor on the same text after an edit
+    // at the start of `line`.
+    fn shift_line_locations(&mut self, line: usize, shift: impl Fn(usize) -> usize) {
+        for location in once(&mut self.cursor).chain(self.selection_anchor.as_mut()) {
+            if location.line == line {
+                location.col = shift(location.col);
+            }
+        }
+    }
+
+    // One level of indentation, in the style of the file around `line`: a tab
+    // where the lines are indented with tabs, and where they're indented with
+    // spaces, as many as a level took there. The settings only decide when
+    // there is no indentation above `line` to go by.
+    fn indent_unit(&self, line: usize) -> String {
+        let indented_line = (0..=line)
+            .rev()
+            .find(|&line| self.line_indent(line).starts_with([' ', '\t']));
+
+        match indented_line {
+            Some(line) if self.line_indent(line).starts_with('\t') => "\t".into(),
+            Some(line) => " ".repeat(self.space_indent_level(line)),
+            None if self.expand_tab => " ".repeat(self.tab_width),
+            None => "\t".into(),
+        }
+    }
+
+    // How many spaces deeper `line` is indented than the nearest line above it
+    // that is indented less. Blank lines are skipped.
+    fn space_indent_level(&self, line: usize) -> usize {
+        let spaces = |line| {
+            self.line_indent(line)
+                .chars()
+                .take_while(|&c| c == ' ')
+                .count()
+        };
+        let depth = spaces(line);
+        let outer_depth = (0..line)
+            .rev()
+            .filter(|&line| !self.buffer.get_line(line).trim().is_empty())
+            .map(spaces)
+            .find(|&outer_depth| outer_depth < depth)
+            .unwrap_or(0);
+        depth - outer_depth
+    }
+
+    // The whitespace a line starts with. Blank lines have no indentation.
+    fn line_indent(&self, line: usize) -> String {
+        let text = self.buffer.get_line(line);
+        if text.trim().is_empty() {
+            return String::new();
         }
+        text.chars().take_while(|c| c.is_whitespace()).collect()
     }
 
     pub fn move_cursor_down(&mut self) {
-        if self.is_cursor_at_buffer_bottom() {
+        if self.wrap != Wrap::Off {
+            let (index, _) = self.cursor_wrapped_row();
+            if index + 1 < self.wrapped_rows(self.cursor.line).len() {
+                self.move_cursor_to_wrapped_row(self.cursor.line, index + 1);
+            } else if !self.is_cursor_at_buffer_bottom() {
+                self.move_cursor_to_wrapped_row(self.cursor.line + 1, 0);
+            }
             return;
         }
 
-        if self.is_cursor_at_view_bottom() {
-            self.move_view_down_unchecked();
+        if self.is_cursor_at_buffer_bottom() {
+            return;
         }
 
         self.cursor.line += 1;
-        self.adjust_cursor_past_eol();
+        self.adjust_cursor_cx_to_rx();
     }
 
     pub fn move_cursor_to_buffer_top(&mut self) {
-        self.move_view_to_buffer_top();
         self.cursor.line = 0;
+        self.adjust_cursor_cx_to_rx();
     }
 
     pub fn move_cursor_to_buffer_bottom(&mut self) {
-        self.move_view_to_buffer_bottom();
-        self.cursor.line = self.rendered_buffer.last_line();
-    }
-
-    fn move_view_to_buffer_top(&mut self) {
-        self.view.line = 0;
-    }
-
-    fn move_view_to_buffer_bottom(&mut self) {
-        self.view.line = self.bottom_most_view_pos()
+        self.cursor.line = self.rendered_buffer().last_line();
+        self.adjust_cursor_cx_to_rx();
     }
 
     fn bottom_most_view_pos(&self) -> usize {
         // TODO! See if the interaction between the rendered buffer and the view
         // can be expressed in a better way.
-        self.rendered_buffer
+        self.rendered_buffer()
             .line_count()
             .saturating_sub(self.view.height)
     }
 
+    // Both of these scroll by the height of the view, as far as the buffer
+    // allows, and keep the cursor in the same row of it. With wrapping on, the
+    // view moves by screen rows.
     pub fn move_one_view_up(&mut self) {
-        let cursor_line_offset = self.cursor.line - self.view.line;
-        self.view.line = self.view.line.saturating_sub(self.view.height);
-        self.cursor.line = self.view.line + cursor_line_offset;
-        self.adjust_cursor_past_eol();
+        self.move_one_view(Self::scroll_view_up_one);
     }
 
     pub fn move_one_view_down(&mut self) {
-        let cursor_line_offset = self.cursor.line - self.view.line;
-        self.view.line = cmp::min(
-            self.view.line + self.view.height,
-            self.bottom_most_view_pos(),
-        );
-        self.cursor.line = self.view.line + cursor_line_offset;
-        self.adjust_cursor_past_eol();
+        self.move_one_view(Self::scroll_view_down_one);
+    }
+
+    fn move_one_view(&mut self, scroll_one: fn(&mut Self) -> bool) {
+        let row = self.get_view_cursor().line;
+        for _ in 0..self.view.height {
+            if !scroll_one(self) {
+                break;
+            }
+        }
+        self.move_cursor_to_view_row(row);
     }
 
     pub fn move_cursor_left(&mut self) {
@@ -253,12 +1328,10 @@ impl Editor {
             self.move_cursor_up();
             self.move_cursor_to_eol_col();
         } else {
-            if self.is_cursor_at_view_left() {
-                self.move_view_left_unchecked();
-            }
-
             self.move_cursor_left_unchecked();
         }
+
+        self.update_cursor_rx();
     }
 
     pub fn move_cursor_right(&mut self) {
@@ -270,62 +1343,212 @@ impl Editor {
             self.move_cursor_down();
             self.move_cursor_to_line_start();
         } else {
-            if self.is_cursor_at_view_right() {
-                self.move_view_right_unchecked();
-            }
-
             self.move_cursor_right_unchecked();
         }
+
+        self.update_cursor_rx();
+    }
+
+    // Moving by word at the start (or the end) of a line goes to the end of
+    // the line before (or the start of the line after) it, like moving by char.
+    pub fn move_cursor_word_left(&mut self) {
+        if self.is_cursor_at_line_start() {
+            self.move_cursor_left();
+        } else {
+            self.cursor.col = self
+                .buffer
+                .prev_word_boundary(self.cursor, &self.word_classes);
+            self.update_cursor_rx();
+        }
+    }
+
+    pub fn move_cursor_word_right(&mut self) {
+        if self.is_cursor_at_eol_col() {
+            self.move_cursor_right();
+        } else {
+            self.cursor.col = self
+                .buffer
+                .next_word_boundary(self.cursor, &self.word_classes);
+            self.update_cursor_rx();
+        }
     }
 
     pub fn move_cursor_to_line_start(&mut self) {
         self.cursor.col = 0;
+        self.update_cursor_rx();
     }
 
     pub fn move_cursor_to_line_end(&mut self) {
-        self.cursor.col = self.rendered_buffer.last_col(self.cursor.line);
+        let eol = Location::new(self.cursor.line, self.buffer.line_len(self.cursor.line));
+        self.cursor.col = self.buffer.prev_grapheme_boundary(eol);
+        self.update_cursor_rx();
     }
 
     fn move_cursor_to_eol_col(&mut self) {
-        self.cursor.col = self.rendered_buffer.eol_col(self.cursor.line);
+        self.cursor.col = self.buffer.line_len(self.cursor.line);
     }
 
-    fn adjust_cursor_past_eol(&mut self) {
-        if self.is_cursor_past_eol() {
-            self.move_cursor_to_eol_col();
-        }
+    // Places the cursor on its new line after a vertical move, as close to the
+    // render column it had before as that line allows.
+    fn adjust_cursor_cx_to_rx(&mut self) {
+        self.cursor.col = self
+            .rendered_buffer()
+            .rx_to_cx(self.cursor.line, self.cursor_rx);
+        self.scroll_into_view();
     }
 
-    fn move_view_up_unchecked(&mut self) {
-        self.view.line -= 1;
+    // The wrapped counterpart of `adjust_cursor_cx_to_rx`, which keeps the
+    // cursor in the same screen column instead. The render column the cursor
+    // should be at is kept relative to the start of the new row, so that it
+    // sticks when passing through shorter rows.
+    fn move_cursor_to_wrapped_row(&mut self, line: usize, index: usize) {
+        let (_, current_row) = self.cursor_wrapped_row();
+        let screen_col = self.cursor_rx.saturating_sub(current_row.start_rx);
+        self.place_cursor_in_wrapped_row(line, index, screen_col);
     }
 
-    fn move_view_down_unchecked(&mut self) {
-        self.view.line += 1;
+    fn place_cursor_in_wrapped_row(&mut self, line: usize, index: usize, screen_col: usize) {
+        let rows = self.wrapped_rows(line);
+        let row = rows[index];
+        self.cursor.line = line;
+        self.cursor_rx = row.start_rx + screen_col;
+
+        // Past the end of a row that isn't the last one, the cursor would end
+        // up at the start of the next row.
+        let col = self.rendered_buffer().rx_to_cx(line, self.cursor_rx);
+        self.cursor.col = if index + 1 < rows.len() && col >= row.end_cx {
+            self.buffer
+                .prev_grapheme_boundary(Location::new(line, row.end_cx))
+        } else {
+            col
+        };
+        self.scroll_into_view();
     }
 
-    fn move_view_left_unchecked(&mut self) {
-        self.view.col -= 1;
+    fn wrapped_rows(&self, line: usize) -> Vec<WrappedRow> {
+        // One column is left for the continuation marker.
+        let width = self.view.width.saturating_sub(1).max(1);
+        self.rendered_buffer()
+            .wrap_line(line, width, self.wrap == Wrap::Words)
     }
 
-    fn move_view_right_unchecked(&mut self) {
-        self.view.col += 1;
+    // Returns the row the cursor is in, along with its index in the line.
+    fn cursor_wrapped_row(&self) -> (usize, WrappedRow) {
+        let rows = self.wrapped_rows(self.cursor.line);
+        let index = rows
+            .iter()
+            .rposition(|row| row.start_cx <= self.cursor.col)
+            .unwrap_or(0);
+        (index, rows[index])
     }
 
-    fn is_cursor_at_view_top(&self) -> bool {
-        self.cursor.line == self.view.line
+    // Has to be called whenever the cursor moves within a line.
+    fn update_cursor_rx(&mut self) {
+        self.cursor_rx = self.rendered_buffer().cx_to_rx(self.cursor);
+        self.scroll_into_view();
     }
 
-    fn is_cursor_at_view_bottom(&self) -> bool {
-        self.cursor.line == self.view.last_line()
-    }
+    // Scrolls the view just enough for the cursor to be in it, along with the
+    // scrolloff margins around it. Every operation that moves the cursor or
+    // changes the buffer ends with this, so the cursor never leaves the view.
+    //
+    // There is no margin to keep past the end of the buffer or the start of a
+    // line. The whole grapheme under the cursor is kept visible, which matters
+    // for wide graphemes at the right edge of the view.
+    fn scroll_into_view(&mut self) {
+        if self.wrap != Wrap::Off {
+            self.scroll_wrapped_into_view();
+            return;
+        }
+
+        let last_line = self.rendered_buffer().last_line();
+        let margin = self
+            .scrolloff
+            .vertical
+            .min(self.view.height.saturating_sub(1) / 2);
+        let margin_above = margin.min(self.cursor.line);
+        let margin_below = margin.min(last_line.saturating_sub(self.cursor.line));
+
+        if self.cursor.line < self.view.line + margin_above {
+            self.view.line = self.cursor.line - margin_above;
+        } else if self.cursor.line + margin_below > self.view.last_line() {
+            self.view.line = (self.cursor.line + margin_below + 1).saturating_sub(self.view.height);
+        }
 
-    fn is_cursor_at_view_left(&self) -> bool {
-        self.cursor.col == self.view.col
+        let next_col = self.buffer.next_grapheme_boundary(self.cursor);
+        let start = self.rendered_buffer().cx_to_rx(self.cursor);
+        let end = self
+            .rendered_buffer()
+            .cx_to_rx(Location::new(self.cursor.line, next_col))
+            .max(start + 1);
+        let margin = self
+            .scrolloff
+            .horizontal
+            .min(self.view.width.saturating_sub(1) / 2);
+        let margin_left = margin.min(start);
+
+        if start < self.view.col + margin_left {
+            self.view.col = start - margin_left;
+        } else if end + margin > self.view.col + self.view.width {
+            // A grapheme wider than the whole view gets its start shown.
+            self.view.col = (end + margin).saturating_sub(self.view.width).min(start);
+        }
     }
 
-    fn is_cursor_at_view_right(&self) -> bool {
-        self.cursor.col == self.view.last_col()
+    // Same as `scroll_into_view`, but the view moves by screen rows and the
+    // margins are counted in them too.
+    fn scroll_wrapped_into_view(&mut self) {
+        let height = self.view.height;
+        let margin = self.scrolloff.vertical.min(height.saturating_sub(1) / 2);
+        let (cursor_index, _) = self.cursor_wrapped_row();
+
+        // Every line takes up at least a row, so lines further than a view
+        // above the cursor can't be in the view.
+        if self.view.line + height < self.cursor.line {
+            self.view.line = self.cursor.line - height;
+            self.view.top_row = 0;
+        }
+        if (self.cursor.line, cursor_index) < (self.view.line, self.view.top_row) {
+            self.view.line = self.cursor.line;
+            self.view.top_row = cursor_index;
+        }
+        let mut top_rows = self.wrapped_rows(self.view.line).len();
+        self.view.top_row = self.view.top_row.min(top_rows - 1);
+
+        let mut rows_above = (self.view.line..self.cursor.line)
+            .map(|line| self.wrapped_rows(line).len())
+            .sum::<usize>()
+            + cursor_index
+            - self.view.top_row;
+        while rows_above < margin && (self.view.line, self.view.top_row) != (0, 0) {
+            if self.view.top_row > 0 {
+                self.view.top_row -= 1;
+            } else {
+                self.view.line -= 1;
+                top_rows = self.wrapped_rows(self.view.line).len();
+                self.view.top_row = top_rows - 1;
+            }
+            rows_above += 1;
+        }
+
+        let mut rows_below = self.wrapped_rows(self.cursor.line).len() - 1 - cursor_index;
+        let mut line = self.cursor.line + 1;
+        while rows_below < margin && line < self.rendered_buffer().line_count() {
+            rows_below += self.wrapped_rows(line).len();
+            line += 1;
+        }
+        let rows_below = rows_below.min(margin);
+
+        while rows_above + 1 + rows_below > height && rows_above > 0 {
+            if self.view.top_row + 1 < top_rows {
+                self.view.top_row += 1;
+            } else {
+                self.view.line += 1;
+                self.view.top_row = 0;
+                top_rows = self.wrapped_rows(self.view.line).len();
+            }
+            rows_above -= 1;
+        }
     }
 
     fn is_cursor_at_buffer_top(&self) -> bool {
@@ -333,7 +1556,7 @@ impl Editor {
     }
 
     fn is_cursor_at_buffer_bottom(&self) -> bool {
-        self.cursor.line == self.rendered_buffer.last_line()
+        self.cursor.line == self.rendered_buffer().last_line()
     }
 
     fn is_cursor_at_line_start(&self) -> bool {
@@ -341,11 +1564,6 @@ impl Editor {
     }
 
     fn is_cursor_at_eol_col(&self) -> bool {
-        eprintln!("{}", self.rendered_buffer.eol_col(self.cursor.line));
-        self.cursor.col == self.rendered_buffer.eol_col(self.cursor.line)
-    }
-
-    fn is_cursor_past_eol(&self) -> bool {
-        self.cursor.col > self.rendered_buffer.eol_col(self.cursor.line)
+        self.cursor.col == self.buffer.line_len(self.cursor.line)
     }
 }
@@ -0,0 +1,149 @@
+use crate::core::Location;
+
+// Cursor and view position captured around a transaction, so that undoing or
+// redoing it puts the user back where the change happened.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub cursor: Location,
+    pub view: Location,
+    pub view_top_row: usize,
+}
+
+// A single reversible change to the buffer. Every edit carries enough data to
+// construct its inverse without looking at the buffer again.
+#[derive(Clone)]
+pub enum Edit {
+    InsertChar(Location, char),
+    RemoveChar(Location, char),
+    // Text within a single line.
+    InsertText(Location, String),
+    RemoveText(Location, String),
+    InsertLine(usize),
+    RemoveLine(usize),
+    // The location is where the line gets split, i.e. where the first line
+    // ends after the split.
+    SplitLine(Location),
+    // The location is the end of the first line before the join.
+    JoinLines(Location),
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Self {
+        match self.clone() {
+            Edit::InsertChar(location, c) => Edit::RemoveChar(location, c),
+            Edit::RemoveChar(location, c) => Edit::InsertChar(location, c),
+            Edit::InsertText(location, text) => Edit::RemoveText(location, text),
+            Edit::RemoveText(location, text) => Edit::InsertText(location, text),
+            Edit::InsertLine(line) => Edit::RemoveLine(line),
+            Edit::RemoveLine(line) => Edit::InsertLine(line),
+            Edit::SplitLine(location) => Edit::JoinLines(location),
+            Edit::JoinLines(location) => Edit::SplitLine(location),
+        }
+    }
+
+    // The first line the edit changes. The lines before it are left alone.
+    pub fn line(&self) -> usize {
+        match *self {
+            Edit::InsertChar(location, _)
+            | Edit::RemoveChar(location, _)
+            | Edit::InsertText(location, _)
+            | Edit::RemoveText(location, _)
+            | Edit::SplitLine(location)
+            | Edit::JoinLines(location) => location.line,
+            Edit::InsertLine(line) | Edit::RemoveLine(line) => line,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TransactionKind {
+    Typing,
+    Deletion,
+    Other,
+}
+
+pub struct Transaction {
+    kind: TransactionKind,
+    pub edits: Vec<Edit>,
+    pub before: Snapshot,
+    pub after: Snapshot,
+}
+
+impl Transaction {
+    fn new(kind: TransactionKind, before: Snapshot) -> Self {
+        Self {
+            kind,
+            edits: Vec::new(),
+            before,
+            after: before,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    // Whether the transaction on top of the undo stack may still be extended
+    // by a following transaction of the same kind.
+    open: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Consecutive typing (or deleting) continues the previous transaction as
+    // long as nothing moved the cursor in between, so that a whole run of
+    // keystrokes is undone in one step.
+    pub fn begin(&mut self, kind: TransactionKind, before: Snapshot) {
+        let continues = self.open
+            && kind != TransactionKind::Other
+            && matches!(
+                self.undo_stack.last(),
+                Some(last) if last.kind == kind && last.after == before
+            );
+
+        if !continues {
+            self.undo_stack.push(Transaction::new(kind, before));
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        self.redo_stack.clear();
+
+        if let Some(transaction) = self.undo_stack.last_mut() {
+            transaction.edits.push(edit);
+        }
+    }
+
+    pub fn end(&mut self, after: Snapshot) {
+        if let Some(transaction) = self.undo_stack.last_mut() {
+            if transaction.edits.is_empty() {
+                self.undo_stack.pop();
+                return;
+            }
+            transaction.after = after;
+        }
+        self.open = true;
+    }
+
+    pub fn pop_undo(&mut self) -> Option<Transaction> {
+        self.open = false;
+        self.undo_stack.pop()
+    }
+
+    pub fn push_undo(&mut self, transaction: Transaction) {
+        self.undo_stack.push(transaction);
+    }
+
+    pub fn pop_redo(&mut self) -> Option<Transaction> {
+        self.open = false;
+        self.redo_stack.pop()
+    }
+
+    pub fn push_redo(&mut self, transaction: Transaction) {
+        self.redo_stack.push(transaction);
+    }
+}
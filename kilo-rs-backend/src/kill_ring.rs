@@ -0,0 +1,39 @@
+use std::collections::VecDeque;
+
+// Texts beyond this many get dropped, oldest first.
+const CAPACITY: usize = 32;
+
+// The history of text that got cut or copied, most recent first. Pasting takes
+// the most recent entry, and can then be cycled through the older ones.
+#[derive(Default)]
+pub struct KillRing {
+    entries: VecDeque<String>,
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Copying the same text twice in a row doesn't fill the ring up with it.
+    pub fn push(&mut self, text: String) {
+        if text.is_empty() || self.entries.front() == Some(&text) {
+            return;
+        }
+
+        self.entries.push_front(text);
+        self.entries.truncate(CAPACITY);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
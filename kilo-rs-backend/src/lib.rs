@@ -1,3 +1,6 @@
 pub mod core;
 pub mod editor;
+pub mod history;
+pub mod kill_ring;
+pub mod save;
 pub mod view;
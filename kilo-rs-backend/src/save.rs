@@ -0,0 +1,171 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{bail, Result};
+
+// Symlink chains longer than this are most likely loops.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+// Temporary file names only collide when leftovers of earlier saves are lying
+// around, so running out of these means something else is wrong.
+const MAX_TEMP_FILE_ATTEMPTS: usize = 100;
+
+#[derive(Clone, Copy, Default)]
+pub struct SaveOptions {
+    // Keep the previous contents of the file next to it, as `file~`.
+    pub backup: bool,
+}
+
+// Replaces the contents of the file at `path` without ever leaving it
+// truncated or half-written: the new contents go to a temporary file in the
+// same directory, which is synced to disk and then renamed over the original.
+// The rename either happens completely or not at all, so a crash or a full
+// disk leaves either the old or the new file behind.
+//
+// Symlinks are followed, so that the file they point to gets replaced instead
+// of the link itself. The permissions and, where possible, the ownership of the
+// original file carry over to the new one. They are applied before anything
+// gets written, so that the contents of a private file are never readable by
+// anyone the original wasn't.
+pub fn write_atomically(
+    path: &Path,
+    options: &SaveOptions,
+    write_contents: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let target = resolve_symlinks(path)?;
+    let original = match fs::metadata(&target) {
+        Ok(metadata) => Some(metadata),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+        Err(error) => return Err(error.into()),
+    };
+
+    let (temp_path, temp_file) = create_temp_file(&target, original.as_ref())?;
+    let result = (|| {
+        // Changing the owner can clear the setuid and setgid bits, so the
+        // permissions come after it.
+        if let Some(metadata) = &original {
+            copy_ownership(&temp_file, metadata)?;
+            temp_file.set_permissions(metadata.permissions())?;
+        }
+
+        let mut writer = BufWriter::new(temp_file);
+        write_contents(&mut writer)?;
+        let file = writer.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+
+        if options.backup && original.is_some() {
+            make_backup(&target)?;
+        }
+        fs::rename(&temp_path, &target)?;
+        sync_parent_dir(&target);
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn resolve_symlinks(path: &Path) -> Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    bail!("Too many levels of symbolic links: {}", path.display())
+}
+
+fn create_temp_file(target: &Path, original: Option<&fs::Metadata>) -> Result<(PathBuf, File)> {
+    let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+    let dir = match target.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    restrict_mode(&mut options, original);
+
+    for attempt in 0..MAX_TEMP_FILE_ATTEMPTS {
+        let temp_path = dir.join(format!(".{file_name}.{}.{attempt}.tmp", process::id()));
+        match options.open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+    bail!(
+        "Could not create a temporary file next to {}",
+        target.display()
+    )
+}
+
+// Creates the temporary file with the original's mode right away, rather than
+// the default one, which is usually readable by everyone.
+#[cfg(unix)]
+fn restrict_mode(options: &mut OpenOptions, original: Option<&fs::Metadata>) {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if let Some(metadata) = original {
+        options.mode(metadata.permissions().mode() & 0o777);
+    }
+}
+
+#[cfg(not(unix))]
+fn restrict_mode(_options: &mut OpenOptions, _original: Option<&fs::Metadata>) {}
+
+// Hard linking keeps the backup around without copying the file, since the
+// rename that follows only swaps out the directory entry of the original. Not
+// every file system supports hard links though.
+fn make_backup(target: &Path) -> Result<()> {
+    let mut backup = target.as_os_str().to_owned();
+    backup.push("~");
+
+    match fs::remove_file(&backup) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+    if fs::hard_link(target, &backup).is_err() {
+        fs::copy(target, &backup)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_ownership(file: &File, metadata: &fs::Metadata) -> Result<()> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    // Only root can give files away, so failing to do so is not an error. The
+    // file then simply ends up owned by the user saving it.
+    match fchown(file, Some(metadata.uid()), Some(metadata.gid())) {
+        Err(error) if error.kind() != io::ErrorKind::PermissionDenied => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_ownership(_file: &File, _metadata: &fs::Metadata) -> Result<()> {
+    Ok(())
+}
+
+// Makes the rename itself durable. Not all platforms allow syncing a directory,
+// and the file contents are already safe at this point, so errors are ignored.
+fn sync_parent_dir(target: &Path) {
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+}
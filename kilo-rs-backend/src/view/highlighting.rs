@@ -0,0 +1,458 @@
+use std::cell::RefCell;
+use std::path::Path;
+
+use crate::core::Buffer;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TokenClass {
+    Normal,
+    Keyword,
+    Type,
+    String,
+    Comment,
+    Number,
+    // The numbers in the gutter aren't tokens, but get styled the same way.
+    LineNumber,
+    CurrentLineNumber,
+}
+
+// The construct spanning multiple lines that a line starts inside of, if any.
+#[derive(Clone, Copy, PartialEq)]
+enum LineState {
+    Normal,
+    BlockComment,
+}
+
+struct Language {
+    extensions: &'static [&'static str],
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    // Whether identifiers starting with an uppercase letter are types, which
+    // is the naming convention in a lot of languages.
+    capitalized_types: bool,
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    // Strings end at the end of the line if they aren't closed before that.
+    string_quotes: &'static [char],
+    // Whether single quotes start char literals, like 'x' or '\'', in a
+    // language where they start other things too, like Rust's lifetimes.
+    char_literals: bool,
+    // The chars that indent the lines after a line ending with them.
+    indent_after: &'static [char],
+}
+
+// Tags the chars of the lines in a buffer with token classes. Highlighting a
+// line requires knowing the state the previous lines leave it in, so those
+// states get cached. An edit only invalidates the states of the lines after
+// it, and the cache is refilled lazily when the lines get highlighted again.
+pub struct Highlighter {
+    language: Option<&'static Language>,
+    // The state each line starts in, for a prefix of the buffer's lines.
+    line_states: RefCell<Vec<LineState>>,
+}
+
+impl Highlighter {
+    // The language is chosen by the file extension. Files in other languages
+    // (and scratch buffers) don't get highlighted.
+    pub fn for_path(file_path: Option<&str>) -> Self {
+        let extension = file_path
+            .and_then(|file_path| Path::new(file_path).extension())
+            .and_then(|extension| extension.to_str());
+        let language = extension.and_then(|extension| {
+            LANGUAGES
+                .iter()
+                .find(|language| language.extensions.contains(&extension))
+        });
+
+        Self {
+            language,
+            line_states: RefCell::new(vec![LineState::Normal]),
+        }
+    }
+
+    // Has to be called whenever the buffer changes, with the first line that
+    // changed. The lines before it keep their states.
+    pub fn invalidate_from(&mut self, line_number: usize) {
+        self.line_states.get_mut().truncate(line_number + 1);
+    }
+
+    // Whether a line ending with `c` gets the lines after it indented further.
+    pub fn indents_after(&self, c: char) -> bool {
+        self.language
+            .is_some_and(|language| language.indent_after.contains(&c))
+    }
+
+    // The bracket that `c` closes, if it's one of the brackets that indent. A
+    // line starting with `c` goes back to the indentation of the line that
+    // bracket was opened on.
+    pub fn opening_bracket(&self, c: char) -> Option<char> {
+        self.language.and_then(|language| {
+            language
+                .indent_after
+                .iter()
+                .copied()
+                .find(|&opening| closing_bracket(opening) == Some(c))
+        })
+    }
+
+    // Returns the token class of every char of the line.
+    pub fn highlight_line(&self, buffer: &Buffer, line_number: usize) -> Vec<TokenClass> {
+        let chars: Vec<char> = buffer.get_line(line_number).chars().collect();
+        match self.language {
+            Some(language) => {
+                let state = self.start_state(language, buffer, line_number);
+                highlight(language, &chars, state).0
+            }
+            None => vec![TokenClass::Normal; chars.len()],
+        }
+    }
+
+    fn start_state(&self, language: &Language, buffer: &Buffer, line_number: usize) -> LineState {
+        let mut line_states = self.line_states.borrow_mut();
+        while line_states.len() <= line_number {
+            let previous = line_states.len() - 1;
+            let chars: Vec<char> = buffer.get_line(previous).chars().collect();
+            let (_, state) = highlight(language, &chars, line_states[previous]);
+            line_states.push(state);
+        }
+        line_states[line_number]
+    }
+}
+
+// Highlights a single line, given the state it starts in. Returns the class of
+// each char along with the state the next line starts in.
+fn highlight(
+    language: &Language,
+    chars: &[char],
+    state: LineState,
+) -> (Vec<TokenClass>, LineState) {
+    let mut classes = vec![TokenClass::Normal; chars.len()];
+    let mut state = state;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+        let is_word_start = i == 0 || !is_identifier_char(chars[i - 1]);
+
+        let (class, len) = if state == LineState::BlockComment {
+            let (_, end) = language.block_comment.unwrap();
+            match find(rest, end) {
+                Some(index) => {
+                    state = LineState::Normal;
+                    (TokenClass::Comment, index + end.chars().count())
+                }
+                None => (TokenClass::Comment, rest.len()),
+            }
+        } else if language
+            .line_comment
+            .is_some_and(|start| starts_with(rest, start))
+        {
+            (TokenClass::Comment, rest.len())
+        } else if let Some((start, _)) = language
+            .block_comment
+            .filter(|(start, _)| starts_with(rest, start))
+        {
+            state = LineState::BlockComment;
+            (TokenClass::Comment, start.chars().count())
+        } else if language.string_quotes.contains(&rest[0]) {
+            (TokenClass::String, string_len(rest))
+        } else if let Some(len) = char_literal_len(language, rest) {
+            (TokenClass::String, len)
+        } else if rest[0].is_ascii_digit() && is_word_start {
+            let len = count_while(rest, |c| is_identifier_char(c) || c == '.');
+            (TokenClass::Number, len)
+        } else if is_identifier_char(rest[0]) && is_word_start {
+            let len = count_while(rest, is_identifier_char);
+            let word: String = rest[..len].iter().collect();
+            let class = if language.keywords.contains(&word.as_str()) {
+                TokenClass::Keyword
+            } else if language.types.contains(&word.as_str())
+                || (language.capitalized_types && rest[0].is_uppercase())
+            {
+                TokenClass::Type
+            } else {
+                TokenClass::Normal
+            };
+            (class, len)
+        } else {
+            (TokenClass::Normal, 1)
+        };
+
+        classes[i..i + len].fill(class);
+        i += len;
+    }
+
+    (classes, state)
+}
+
+// The length of the string starting with the quote at the start of `chars`,
+// including both quotes. Quotes escaped with a backslash don't end it.
+fn string_len(chars: &[char]) -> usize {
+    let quote = chars[0];
+    let mut len = 1;
+    while len < chars.len() {
+        match chars[len] {
+            '\\' => len += 2,
+            c if c == quote => return len + 1,
+            _ => len += 1,
+        }
+    }
+    chars.len()
+}
+
+// The length of the char literal starting at the start of `chars`, if there
+// is one. A quote that isn't closed right after a single (possibly escaped)
+// char starts a lifetime or a label instead.
+fn char_literal_len(language: &Language, chars: &[char]) -> Option<usize> {
+    if !language.char_literals || chars[0] != '\'' {
+        return None;
+    }
+    match chars.get(1)? {
+        '\\' => chars
+            .iter()
+            .skip(3)
+            .position(|&c| c == '\'')
+            .map(|index| index + 4),
+        _ => (chars.get(2) == Some(&'\'')).then_some(3),
+    }
+}
+
+fn closing_bracket(opening: char) -> Option<char> {
+    match opening {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn count_while(chars: &[char], predicate: impl Fn(char) -> bool) -> usize {
+    chars.iter().take_while(|&&c| predicate(c)).count()
+}
+
+fn starts_with(chars: &[char], prefix: &str) -> bool {
+    let mut chars = chars.iter();
+    prefix.chars().all(|p| chars.next() == Some(&p))
+}
+
+fn find(chars: &[char], needle: &str) -> Option<usize> {
+    (0..chars.len()).find(|&index| starts_with(&chars[index..], needle))
+}
+
+static LANGUAGES: &[Language] = &[
+    Language {
+        extensions: &["rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+            "f32", "f64", "bool", "char", "str",
+        ],
+        capitalized_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        // Single quotes also start lifetimes, so char literals get told apart
+        // from them separately.
+        string_quotes: &['"'],
+        char_literals: true,
+        indent_after: &['{', '(', '['],
+    },
+    Language {
+        extensions: &["c", "h", "cc", "cpp", "cxx", "hh", "hpp"],
+        keywords: &[
+            "auto",
+            "break",
+            "case",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "enum",
+            "extern",
+            "false",
+            "for",
+            "goto",
+            "if",
+            "inline",
+            "namespace",
+            "new",
+            "nullptr",
+            "private",
+            "protected",
+            "public",
+            "register",
+            "restrict",
+            "return",
+            "sizeof",
+            "static",
+            "struct",
+            "switch",
+            "template",
+            "this",
+            "true",
+            "typedef",
+            "typename",
+            "union",
+            "using",
+            "virtual",
+            "volatile",
+            "while",
+        ],
+        types: &[
+            "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned",
+            "bool", "size_t", "ssize_t", "int8_t", "int16_t", "int32_t", "int64_t", "uint8_t",
+            "uint16_t", "uint32_t", "uint64_t",
+        ],
+        capitalized_types: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\''],
+        char_literals: false,
+        indent_after: &['{', '(', '['],
+    },
+    Language {
+        extensions: &["py"],
+        keywords: &[
+            "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+            "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
+            "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise",
+            "return", "try", "while", "with", "yield",
+        ],
+        types: &[
+            "bool", "bytes", "dict", "float", "int", "list", "object", "set", "str", "tuple",
+        ],
+        capitalized_types: true,
+        line_comment: Some("#"),
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        char_literals: false,
+        indent_after: &[':', '(', '[', '{'],
+    },
+    Language {
+        extensions: &["js", "mjs", "cjs", "jsx", "ts", "tsx"],
+        keywords: &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "debugger",
+            "default",
+            "delete",
+            "do",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "instanceof",
+            "let",
+            "new",
+            "null",
+            "return",
+            "static",
+            "super",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+            "with",
+            "yield",
+        ],
+        types: &[
+            "any", "bigint", "boolean", "never", "number", "object", "string", "symbol", "unknown",
+        ],
+        capitalized_types: true,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\'', '`'],
+        char_literals: false,
+        indent_after: &['{', '(', '['],
+    },
+    Language {
+        extensions: &["go"],
+        keywords: &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "fallthrough",
+            "false",
+            "for",
+            "func",
+            "go",
+            "goto",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "nil",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "true",
+            "type",
+            "var",
+        ],
+        types: &[
+            "bool",
+            "byte",
+            "complex64",
+            "complex128",
+            "error",
+            "float32",
+            "float64",
+            "int",
+            "int8",
+            "int16",
+            "int32",
+            "int64",
+            "rune",
+            "string",
+            "uint",
+            "uint8",
+            "uint16",
+            "uint32",
+            "uint64",
+            "uintptr",
+        ],
+        capitalized_types: false,
+        line_comment: Some("//"),
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\'', '`'],
+        char_literals: false,
+        indent_after: &['{', '(', '['],
+    },
+];
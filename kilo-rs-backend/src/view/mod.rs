@@ -1,3 +1,4 @@
+pub mod highlighting;
 pub mod rendering;
 
 // I think keeping this as close to POD as possible is fine. If I want to create
@@ -9,16 +10,49 @@ pub mod rendering;
 // Kind of like an implementation detail and not a part of some API.
 pub struct ViewGeometry {
     pub line: usize,
+    // Stays 0 while lines are wrapped, since they don't get scrolled
+    // horizontally then.
     pub col: usize,
+    // The first screen row of `line` that is in the view, while lines are
+    // wrapped. Stays 0 otherwise.
+    pub top_row: usize,
     pub width: usize,
     pub height: usize,
 }
 
+// How many lines (columns) to keep visible above and below (left and right of)
+// the cursor, as far as the size of the view allows.
+#[derive(Clone, Copy, Default)]
+pub struct Scrolloff {
+    pub vertical: usize,
+    pub horizontal: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Wrap {
+    #[default]
+    Off,
+    // Lines get split at any grapheme.
+    Chars,
+    // Lines get split after whitespace, unless a word is wider than the view.
+    Words,
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    Absolute,
+    // Counted from the cursor line, which shows its absolute number.
+    Relative,
+}
+
 impl ViewGeometry {
     pub fn new(line: usize, col: usize, width: usize, height: usize) -> Self {
         Self {
             line,
             col,
+            top_row: 0,
             width,
             height,
         }
@@ -1,54 +1,199 @@
-use std::iter;
+use std::ops::Range;
 
-use crate::core::Buffer;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
-const TAB_STOP: usize = 8;
+use super::highlighting::{Highlighter, TokenClass};
+use crate::core::{Buffer, Location};
 
-pub struct RenderedBuffer {
-    lines: Vec<String>,
+pub const DEFAULT_TAB_WIDTH: usize = 8;
+
+// Shown in the last column of the screen rows that a wrapped line continues
+// after.
+const WRAP_MARKER: &str = "\\";
+
+// Lines are rendered on demand, straight from the buffer, instead of keeping a
+// rendered copy of the whole file around. Only the lines that are actually
+// looked at (the visible ones, or the one the cursor is on) get rendered.
+//
+// Rendered columns are terminal cells: a wide character (most CJK text, most
+// emoji) takes up two of them, a combining mark takes up none.
+pub struct RenderedBuffer<'a> {
+    buffer: &'a Buffer,
+    tab_width: usize,
 }
 
-impl From<&Buffer> for RenderedBuffer {
-    fn from(buffer: &Buffer) -> Self {
-        Self {
-            lines: buffer.lines().map(|line| render_line(line)).collect(),
-        }
-    }
+// A run of rendered text in a single token class, either all of it selected
+// or none of it.
+#[derive(Clone)]
+pub struct Span {
+    pub text: String,
+    pub class: TokenClass,
+    pub selected: bool,
 }
 
-impl RenderedBuffer {
-    pub fn update_line(&mut self, line_number: usize, buffer: &Buffer) {
-        self.lines[line_number] = render_line(buffer.get_line(line_number));
+pub type StyledLine = Vec<Span>;
+
+// One of the screen rows a line is split into when wrapping: the buffer and
+// render columns it starts and ends at.
+#[derive(Clone, Copy)]
+pub struct WrappedRow {
+    pub start_cx: usize,
+    pub end_cx: usize,
+    pub start_rx: usize,
+    pub end_rx: usize,
+}
+
+impl<'a> RenderedBuffer<'a> {
+    // Tabs are as wide as it takes to get to the next multiple of `tab_width`.
+    pub fn new(buffer: &'a Buffer, tab_width: usize) -> Self {
+        Self { buffer, tab_width }
     }
 
-    pub fn insert_line(&mut self, line_number: usize, buffer: &Buffer) {
-        self.lines
-            .insert(line_number, render_line(buffer.get_line(line_number)));
+    pub fn get_view(
+        &self,
+        highlighter: &Highlighter,
+        selection: Option<(Location, Location)>,
+        line: usize,
+        col: usize,
+        width: usize,
+        height: usize,
+    ) -> Vec<StyledLine> {
+        let end = self.line_count().min(line + height);
+        (line..end)
+            .map(|line| {
+                let classes = highlighter.highlight_line(self.buffer, line);
+                let selected = selected_cols(selection, line);
+                let text = self.buffer.get_line(line);
+                render_columns(&text, self.tab_width, &classes, &selected, col, width)
+            })
+            .collect()
     }
 
-    pub fn remove_line(&mut self, line_number: usize) {
-        self.lines.remove(line_number);
+    // Like `get_view`, but with lines wrapped into screen rows of `width`
+    // cells, the last of which is taken up by the continuation marker. The view
+    // starts at the `row`th screen row of `line`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_wrapped_view(
+        &self,
+        highlighter: &Highlighter,
+        selection: Option<(Location, Location)>,
+        line: usize,
+        row: usize,
+        width: usize,
+        height: usize,
+        at_words: bool,
+    ) -> Vec<StyledLine> {
+        let wrap_width = width.saturating_sub(1).max(1);
+
+        let mut rows = Vec::new();
+        let mut skipped_rows = row;
+        for line in line..self.line_count() {
+            let text = self.buffer.get_line(line);
+            let classes = highlighter.highlight_line(self.buffer, line);
+            let selected = selected_cols(selection, line);
+
+            let wrapped_rows = self.wrap_line(line, wrap_width, at_words);
+            for (index, row) in wrapped_rows.iter().enumerate().skip(skipped_rows) {
+                if rows.len() == height {
+                    return rows;
+                }
+
+                let row_width = row.end_rx - row.start_rx;
+                let mut spans = render_columns(
+                    &text,
+                    self.tab_width,
+                    &classes,
+                    &selected,
+                    row.start_rx,
+                    row_width,
+                );
+                if index + 1 < wrapped_rows.len() {
+                    let padding = " ".repeat(wrap_width.saturating_sub(row_width));
+                    push_text(&mut spans, &padding, TokenClass::Normal, false);
+                    push_text(&mut spans, WRAP_MARKER, TokenClass::Normal, false);
+                }
+                rows.push(spans);
+            }
+            skipped_rows = 0;
+        }
+        rows
     }
 
-    pub fn get_view(&self, line: usize, col: usize, width: usize, height: usize) -> Vec<String> {
-        self.lines
+    // Splits a line into screen rows that are at most `width` cells wide. With
+    // `at_words`, rows end after whitespace where possible, so that words
+    // don't get split. Graphemes wider than a whole row get one of their own.
+    pub fn wrap_line(&self, line: usize, width: usize, at_words: bool) -> Vec<WrappedRow> {
+        let text = self.buffer.get_line(line);
+        let cells: Vec<Cell> = layout(&text, self.tab_width).collect();
+
+        let mut starts = vec![0];
+        let mut break_opportunity = None;
+        for (index, cell) in cells.iter().enumerate() {
+            let is_whitespace = cell.grapheme.chars().all(char::is_whitespace);
+            loop {
+                let row_start = *starts.last().unwrap();
+                if index == row_start || cell.rx + cell.width - cells[row_start].rx <= width {
+                    break;
+                }
+
+                // Whitespace that doesn't fit can start the next row itself.
+                let row_end = match break_opportunity {
+                    Some(opportunity) if at_words && !is_whitespace && opportunity > row_start => {
+                        opportunity
+                    }
+                    _ => index,
+                };
+                starts.push(row_end);
+                break_opportunity = None;
+            }
+
+            if is_whitespace {
+                break_opportunity = Some(index + 1);
+            }
+        }
+
+        let line_len = text.chars().count();
+        let line_width = cells.last().map_or(0, |cell| cell.rx + cell.width);
+        let cx_at = |index: usize| cells.get(index).map_or(line_len, |cell| cell.cx);
+        let rx_at = |index: usize| cells.get(index).map_or(line_width, |cell| cell.rx);
+
+        let ends = starts.iter().skip(1).copied().chain(Some(cells.len()));
+        starts
             .iter()
-            .skip(line)
-            .take(height)
-            .map(|line| line.chars().skip(col).take(width).collect())
+            .zip(ends)
+            .map(|(&start, end)| WrappedRow {
+                start_cx: cx_at(start),
+                end_cx: cx_at(end),
+                start_rx: rx_at(start),
+                end_rx: rx_at(end),
+            })
             .collect()
     }
 
-    pub fn eol_col(&self, line: usize) -> usize {
-        self.lines[line].len()
+    // Converts a buffer column (kilo's cx) into the render column (kilo's rx)
+    // at which the grapheme starting there is displayed.
+    pub fn cx_to_rx(&self, location: Location) -> usize {
+        let line = self.buffer.get_line(location.line);
+        let rx = layout(&line, self.tab_width)
+            .take_while(|cell| cell.cx < location.col)
+            .fold(0, |_, cell| cell.rx + cell.width);
+        rx
     }
 
-    pub fn last_col(&self, line: usize) -> usize {
-        self.eol_col(line).saturating_sub(1)
+    // Converts a render column back into the buffer column of the grapheme
+    // displayed there. Render columns that fall inside of a tab or a wide
+    // grapheme map to its start, ones past the end of the line to its end.
+    pub fn rx_to_cx(&self, line: usize, rx: usize) -> usize {
+        let line = self.buffer.get_line(line);
+        let cx = layout(&line, self.tab_width)
+            .find(|cell| cell.rx + cell.width > rx)
+            .map(|cell| cell.cx);
+        cx.unwrap_or_else(|| line.chars().count())
     }
 
     pub fn line_count(&self) -> usize {
-        self.lines.len()
+        self.buffer.line_count()
     }
 
     pub fn last_line(&self) -> usize {
@@ -56,15 +201,92 @@ impl RenderedBuffer {
     }
 }
 
-fn render_line(line: &str) -> String {
-    let mut rendered = String::new();
-    for c in line.chars() {
-        if c == '\t' {
-            let count = TAB_STOP - (rendered.len() % TAB_STOP);
-            rendered.extend(iter::repeat(' ').take(count));
-        } else {
-            rendered.push(c);
+struct Cell<'a> {
+    cx: usize,
+    rx: usize,
+    width: usize,
+    grapheme: &'a str,
+}
+
+// Lays out the graphemes of a line, pairing each one with its buffer and render
+// columns.
+fn layout(line: &str, tab_width: usize) -> impl Iterator<Item = Cell<'_>> {
+    line.graphemes(true)
+        .scan((0, 0), move |(cx, rx), grapheme| {
+            let width = match grapheme {
+                "\t" => tab_width - (*rx % tab_width),
+                _ => grapheme.width(),
+            };
+            let cell = Cell {
+                cx: *cx,
+                rx: *rx,
+                width,
+                grapheme,
+            };
+
+            *cx += grapheme.chars().count();
+            *rx += width;
+            Some(cell)
+        })
+}
+
+// Renders the `[start, start + width)` range of cells of a line, with `classes`
+// holding the token class of each of its chars and `selected` the range of
+// chars that are selected. Tabs and wide graphemes that
+// only partially fit into the range are replaced by spaces, so that the result
+// is always exactly as wide as the visible part of the line.
+fn render_columns(
+    line: &str,
+    tab_width: usize,
+    classes: &[TokenClass],
+    selected: &Range<usize>,
+    start: usize,
+    width: usize,
+) -> StyledLine {
+    let end = start + width;
+
+    let mut spans = Vec::new();
+    for cell in layout(line, tab_width) {
+        if cell.rx >= end {
+            break;
         }
+
+        let class = classes[cell.cx];
+        let is_selected = selected.contains(&cell.cx);
+        let next_rx = cell.rx + cell.width;
+        if cell.rx >= start && next_rx <= end && cell.grapheme != "\t" {
+            push_text(&mut spans, cell.grapheme, class, is_selected);
+        } else if next_rx > start {
+            let visible = next_rx.min(end) - cell.rx.max(start);
+            push_text(&mut spans, &" ".repeat(visible), class, is_selected);
+        }
+    }
+    spans
+}
+
+// The range of columns of a line that the selection covers, if any.
+fn selected_cols(selection: Option<(Location, Location)>, line: usize) -> Range<usize> {
+    selection
+        .filter(|(start, end)| (start.line..=end.line).contains(&line))
+        .map(|(start, end)| {
+            let start_col = if line == start.line { start.col } else { 0 };
+            let end_col = if line == end.line {
+                end.col
+            } else {
+                usize::MAX
+            };
+            start_col..end_col
+        })
+        .unwrap_or_default()
+}
+
+fn push_text(spans: &mut StyledLine, text: &str, class: TokenClass, selected: bool) {
+    match spans.last_mut() {
+        Some(span) if span.class == class && span.selected == selected => span.text.push_str(text),
+        _ => spans.push(Span {
+            text: String::from(text),
+            class,
+            selected,
+        }),
     }
-    rendered
 }
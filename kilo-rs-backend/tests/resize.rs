@@ -0,0 +1,49 @@
+use kilo_rs_backend::editor::Editor;
+use kilo_rs_backend::view::{LineNumbers, Wrap};
+
+fn editor_with_text(text: &str, wrap: Wrap) -> Editor {
+    let mut editor = Editor::new(80, 10);
+    editor.set_line_numbers(LineNumbers::Absolute);
+    editor.set_wrap(wrap);
+    for c in text.chars() {
+        editor.insert_char(c);
+    }
+    editor
+}
+
+// Resizes to `width` and walks the cursor across the whole line, checking that
+// it stays inside of the view. With wrapping on, the cursor at the end of a
+// line may also be in the column kept for the continuation marker.
+fn check_narrow_width(width: usize, wrap: Wrap) {
+    let mut editor = editor_with_text("some text 漢字 and more", wrap);
+    editor.move_cursor_to_line_start();
+    editor.set_size(width, 10);
+    assert!(editor.get_view_width() >= 1);
+
+    for _ in 0..30 {
+        editor.move_cursor_right();
+        let cursor = editor.get_view_cursor();
+        let right = editor.get_gutter_width() + editor.get_view_width();
+        assert!(cursor.col <= right, "cursor at {} past {right}", cursor.col);
+        assert!(editor.get_view_contents().count() == 10);
+    }
+}
+
+#[test]
+fn zero_width_keeps_the_cursor_in_view() {
+    check_narrow_width(0, Wrap::Off);
+    check_narrow_width(0, Wrap::Chars);
+}
+
+#[test]
+fn width_below_the_gutter_hides_it() {
+    for wrap in [Wrap::Off, Wrap::Chars] {
+        let mut editor = editor_with_text("text", wrap);
+        editor.set_size(2, 10);
+        assert_eq!(editor.get_gutter_width(), 0);
+        assert_eq!(editor.get_view_width(), 2);
+
+        check_narrow_width(1, wrap);
+        check_narrow_width(2, wrap);
+    }
+}
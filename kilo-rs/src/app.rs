@@ -3,15 +3,18 @@ use std::io::Write;
 use anyhow::Result;
 
 use crossterm::{
-    event::{KeyCode, KeyEvent, KeyModifiers},
+    event::{KeyEvent, MouseEvent},
     terminal,
 };
 
 use kilo_rs_backend::editor::Editor;
 
 use crate::{
-    bottom_bar::{BottomBarComponent, BottomBarMessage, PromptKind},
+    bottom_bar::{BottomBarComponent, BottomBarMessage, NotificationKind, PromptKind},
+    clipboard::{self, ClipboardComponent, ClipboardMessage},
+    config::Config,
     editor_controller::{EditorControllerComponent, EditorControllerMessage},
+    keymap::{Command, KeyLookup, Keymap},
     runner::{MessageQueue, ShouldQuit},
     shared::{Rectangle, SharedContext},
     term_utils::Cursor,
@@ -22,6 +25,7 @@ pub enum AppMessage {
     EditorControllerMessage(EditorControllerMessage),
     TextAreaMessage(TextAreaMessage),
     BottomBarMessage(BottomBarMessage),
+    ClipboardMessage(ClipboardMessage),
     SwitchFocus(Focus),
 }
 
@@ -43,6 +47,12 @@ impl From<BottomBarMessage> for AppMessage {
     }
 }
 
+impl From<ClipboardMessage> for AppMessage {
+    fn from(message: ClipboardMessage) -> Self {
+        Self::ClipboardMessage(message)
+    }
+}
+
 impl From<Focus> for AppMessage {
     fn from(focus: Focus) -> Self {
         Self::SwitchFocus(focus)
@@ -54,7 +64,10 @@ pub struct App {
     editor_controller: EditorControllerComponent,
     text_area: TextAreaComponent,
     bottom_bar: BottomBarComponent,
+    clipboard: ClipboardComponent,
+    keymap: Keymap,
     focus: Focus,
+    remaining_quit_presses: usize,
 }
 
 pub enum Focus {
@@ -62,6 +75,9 @@ pub enum Focus {
     BottomBar,
 }
 
+// How many times quit has to be pressed in a row to quit with unsaved changes.
+const QUIT_PRESSES: usize = 3;
+
 #[derive(Default)]
 pub struct StartupArgs {
     pub file: Option<String>,
@@ -70,35 +86,72 @@ pub struct StartupArgs {
 impl App {
     pub fn new(args: StartupArgs) -> Result<Self> {
         let (width, height) = terminal::size()?;
-        let rect = Rectangle::new(0, 0, width, height);
+        Ok(Self::with_size(args, width, height))
+    }
 
-        let mut context = SharedContext {
-            editor: Editor::new(width as usize, height.saturating_sub(1) as usize),
+    fn with_size(args: StartupArgs, width: u16, height: u16) -> Self {
+        // A broken config file is reported, but the editor still starts, with
+        // the default settings.
+        let (config, config_error) = match Config::load() {
+            Ok(config) => (config, None),
+            Err(error) => (Config::default(), Some(error)),
         };
 
-        if let Some(file_path) = args.file {
-            context.editor.open_file(&file_path)?;
-        }
+        let mut editor = Editor::new(width as usize, height.saturating_sub(1) as usize);
+        editor.set_tab_width(config.tab_width);
+        editor.set_expand_tab(config.expand_tab);
+        editor.set_scrolloff(config.scrolloff());
+        editor.set_line_numbers(config.line_numbers());
+        editor.set_wrap(config.wrap());
+        editor.set_auto_indent(config.auto_indent);
+        editor.set_word_classes(config.word_classes());
+        editor.set_save_options(config.save_options());
+
+        let (keymap, keymap_problems) = Keymap::new(&config.keys);
+        let mut context = SharedContext { editor, config };
+
+        // Failing to open the file is not fatal, the editor just starts out
+        // with a scratch buffer instead.
+        let open_error = args
+            .file
+            .and_then(|file_path| context.editor.open_file(&file_path).err());
 
         let editor_controller = EditorControllerComponent::new();
         let text_area = TextAreaComponent::new(&context);
-        let bottom_bar = BottomBarComponent::new(
-            Rectangle {
-                top: rect.bottom,
-                left: rect.left,
-                bottom: rect.bottom,
-                right: rect.right,
-            },
-            &context,
-        );
+        let mut bottom_bar = BottomBarComponent::new(bottom_bar_rect(width, height), &context);
+        if let Some(error) = config_error {
+            bottom_bar.display_notification(NotificationKind::Error(format!(
+                "The config file could not be loaded: {error}"
+            )));
+        }
+        if !keymap_problems.is_empty() {
+            bottom_bar.display_notification(NotificationKind::Warning(format!(
+                "Some key bindings were left out: {}",
+                keymap_problems.join("; ")
+            )));
+        }
+        if let Some(error) = open_error {
+            bottom_bar.display_notification(NotificationKind::Error(format!(
+                "The file could not be opened: {error}"
+            )));
+        }
+        if context.editor.has_mixed_line_endings() {
+            bottom_bar.display_notification(NotificationKind::Warning(format!(
+                "The file has mixed line endings, saving converts them all to {}",
+                context.editor.get_line_ending().name()
+            )));
+        }
 
-        Ok(Self {
+        Self {
             context,
             editor_controller,
             text_area,
             bottom_bar,
+            clipboard: ClipboardComponent::new(clipboard::detect_provider()),
+            keymap,
             focus: Focus::TextArea,
-        })
+            remaining_quit_presses: QUIT_PRESSES,
+        }
     }
 
     pub fn update(&mut self, message: AppMessage, queue: &mut MessageQueue) -> Result<()> {
@@ -111,15 +164,17 @@ impl App {
             }
             TextAreaMessage(message) => self.text_area.update(message)?,
             BottomBarMessage(message) => self.bottom_bar.update(message, queue)?,
+            ClipboardMessage(message) => self.clipboard.update(message, queue)?,
             SwitchFocus(focus) => self.focus = focus,
         }
 
         Ok(())
     }
 
-    pub fn render(&self, writer: &mut impl Write) -> Result<()> {
+    pub fn render(&mut self, writer: &mut impl Write) -> Result<()> {
         self.text_area.render(writer)?;
         self.bottom_bar.render(writer)?;
+        self.clipboard.render(writer)?;
 
         Ok(())
     }
@@ -127,31 +182,189 @@ impl App {
     pub fn cursor(&self) -> Option<Cursor> {
         match self.focus {
             Focus::TextArea => self.text_area.cursor(),
-            Focus::BottomBar => self.bottom_bar.cursor(),
+            Focus::BottomBar => self.bottom_bar.cursor().or_else(|| self.text_area.cursor()),
+        }
+    }
+
+    // The text area takes up all of the terminal but its last row, which is
+    // the bottom bar's.
+    pub fn process_resize(&mut self, width: u16, height: u16, queue: &mut MessageQueue) {
+        queue.push_front(EditorControllerMessage::Resize {
+            width: width as usize,
+            height: height.saturating_sub(1) as usize,
+        });
+        queue.push_front(BottomBarMessage::Resize(bottom_bar_rect(width, height)));
+    }
+
+    // Mouse events go to the component under the pointer. The text area only
+    // takes them while it has the focus, so that clicking around doesn't
+    // interfere with an open prompt.
+    pub fn process_mouse_event(
+        &mut self,
+        event: MouseEvent,
+        queue: &mut MessageQueue,
+    ) -> Result<()> {
+        if self.bottom_bar.contains(event.column, event.row) {
+            self.bottom_bar.process_mouse_event(event);
+            Ok(())
+        } else if matches!(self.focus, Focus::TextArea) {
+            self.text_area.process_mouse_event(event, queue)
+        } else {
+            Ok(())
         }
     }
 
-    #[allow(unused_imports)]
     pub fn process_event(
         &mut self,
         event: KeyEvent,
         queue: &mut MessageQueue,
     ) -> Result<ShouldQuit> {
-        use KeyCode::*;
-        use KeyModifiers as KM;
+        let is_text_area_focused = matches!(self.focus, Focus::TextArea);
+        let lookup = self
+            .keymap
+            .process_key(event, |command| is_text_area_focused || command.is_global());
+
+        if !matches!(lookup, KeyLookup::Command(Command::Quit, _)) {
+            self.remaining_quit_presses = QUIT_PRESSES;
+        }
+
+        let (command, keys) = match lookup {
+            KeyLookup::Command(command, keys) => (command, keys),
+            KeyLookup::Pending => return Ok(ShouldQuit::No),
+            KeyLookup::Unbound(keys) => match keys[..] {
+                [event] => {
+                    match self.focus {
+                        Focus::TextArea => self.text_area.process_event(event, queue)?,
+                        Focus::BottomBar => self.bottom_bar.process_event(event, queue)?,
+                    }
+                    return Ok(ShouldQuit::No);
+                }
+                // A key that doesn't continue the chord cancels it, and then
+                // counts on its own, as if the chord had never been started.
+                [.., event] => return self.process_event(event, queue),
+                [] => return Ok(ShouldQuit::No),
+            },
+        };
+
+        // Another prompt replacing the search one cancels the search, like Esc
+        // in it would, so that the cursor goes back to where it started. Pushed
+        // last, the cancellation gets processed before the new prompt opens.
+        let cancels_search = self.bottom_bar.is_searching()
+            && matches!(
+                command,
+                Command::SaveAs | Command::Search | Command::GoToLine | Command::Replace
+            );
+
+        match command {
+            Command::Quit => {
+                if !self.context.editor.is_modified() {
+                    return Ok(ShouldQuit::Yes);
+                }
+
+                self.remaining_quit_presses -= 1;
+                if self.remaining_quit_presses == 0 {
+                    return Ok(ShouldQuit::Yes);
+                }
 
-        let KeyEvent { modifiers, code } = event;
-        match (modifiers, code) {
-            (KM::CONTROL, Char('q')) => return Ok(ShouldQuit::Yes),
-            (mods, Char('s')) if mods == KM::CONTROL | KM::ALT => {
+                queue.push_front(BottomBarMessage::DisplayNotification(
+                    NotificationKind::UnsavedChanges {
+                        remaining_presses: self.remaining_quit_presses,
+                        quit_keys: keys,
+                    },
+                ));
+            }
+            Command::Save => {
+                queue.push_front(EditorControllerMessage::Save);
+            }
+            // Replacing the confirmation prompt with another one would leave
+            // the replace session running with nothing to end it.
+            Command::SaveAs | Command::Search | Command::GoToLine | Command::Replace
+                if self.bottom_bar.is_confirming_replace() => {}
+            Command::SaveAs => {
                 queue.push_front(BottomBarMessage::DisplayPrompt(PromptKind::SaveAs));
             }
-            _ => match self.focus {
-                Focus::TextArea => self.text_area.process_event(event, queue)?,
-                Focus::BottomBar => self.bottom_bar.process_event(event, queue)?,
-            },
+            Command::Search => {
+                queue.push_front(BottomBarMessage::DisplayPrompt(PromptKind::Search));
+                queue.push_front(EditorControllerMessage::StartSearch);
+            }
+            Command::GoToLine => {
+                queue.push_front(BottomBarMessage::DisplayPrompt(PromptKind::GoToLine));
+            }
+            Command::Replace => {
+                queue.push_front(BottomBarMessage::DisplayPrompt(PromptKind::ReplacePattern));
+            }
+            Command::Editor(message) => queue.push_front(message),
+        }
+
+        if cancels_search {
+            queue.push_front(EditorControllerMessage::CancelSearch);
         }
 
         Ok(ShouldQuit::No)
     }
 }
+
+fn bottom_bar_rect(width: u16, height: u16) -> Rectangle {
+    let rect = Rectangle::new(0, 0, width.max(1), height.max(1));
+    Rectangle {
+        top: rect.bottom,
+        left: rect.left,
+        bottom: rect.bottom,
+        right: rect.right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    fn press(app: &mut App, code: KeyCode, modifiers: KeyModifiers) {
+        let mut queue = MessageQueue::new();
+        app.process_event(KeyEvent { code, modifiers }, &mut queue)
+            .unwrap();
+        while let Some(message) = queue.pop_front() {
+            app.update(message, &mut queue).unwrap();
+        }
+    }
+
+    fn cursor(app: &App) -> (usize, usize) {
+        let cursor = app.context.editor.get_buffer_cursor();
+        (cursor.line, cursor.col)
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn opening_another_prompt_cancels_the_search() {
+        let path = std::env::temp_dir().join("kilo-rs-search-prompt-test.txt");
+        std::fs::write(&path, "first\nsecond\nthird\n").unwrap();
+        let args = StartupArgs {
+            file: Some(path.to_string_lossy().into_owned()),
+        };
+        let mut app = App::with_size(args, 40, 10);
+
+        for command in ['g', 'r', 'f'] {
+            press(&mut app, KeyCode::Char('f'), KeyModifiers::CONTROL);
+            type_text(&mut app, "third");
+            assert_eq!(cursor(&app), (2, 0));
+
+            press(&mut app, KeyCode::Char(command), KeyModifiers::CONTROL);
+            assert_eq!(cursor(&app), (0, 0));
+            press(&mut app, KeyCode::Esc, KeyModifiers::NONE);
+        }
+
+        // The search opened over the search is a fresh one.
+        press(&mut app, KeyCode::Char('f'), KeyModifiers::CONTROL);
+        type_text(&mut app, "second");
+        press(&mut app, KeyCode::Char('f'), KeyModifiers::CONTROL);
+        press(&mut app, KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(cursor(&app), (0, 0));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -3,9 +3,10 @@ use std::time::Instant;
 
 use anyhow::Result;
 use crossterm::cursor::MoveTo;
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 use crossterm::queue;
 use crossterm::style::{PrintStyledContent, Stylize};
+use kilo_rs_backend::core::{LineEnding, SearchDirection};
 
 use crate::app::Focus;
 use crate::editor_controller::EditorControllerMessage;
@@ -17,20 +18,62 @@ pub enum BottomBarMessage {
     UpdateStatus(StatusUpdate),
     DisplayPrompt(PromptKind),
     DisplayNotification(NotificationKind),
+    ClosePrompt,
+    Resize(Rectangle),
 }
 
 pub struct StatusUpdate {
     pub file_name: Option<String>,
+    pub modified: bool,
+    pub line_ending: LineEnding,
     pub cursor_line: usize,
     pub line_count: usize,
 }
 
 pub enum PromptKind {
     SaveAs,
+    Search,
+    ReplacePattern,
+    // Holds the pattern entered in the previous prompt.
+    Replacement(String),
+    // Asks whether to replace the match under the cursor. It takes single key
+    // presses instead of text input, and stays open until the editor controller
+    // closes it once there are no more matches.
+    ConfirmReplace,
+    GoToLine,
 }
 
 pub enum NotificationKind {
     SaveSuccess,
+    ReplaceSuccess {
+        count: usize,
+    },
+    // The keys are the ones that were pressed to quit.
+    UnsavedChanges {
+        remaining_presses: usize,
+        quit_keys: String,
+    },
+    Warning(String),
+    Error(String),
+}
+
+#[derive(Clone, Copy)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl NotificationKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            NotificationKind::SaveSuccess => Severity::Info,
+            NotificationKind::ReplaceSuccess { .. } => Severity::Info,
+            NotificationKind::UnsavedChanges { .. } => Severity::Warning,
+            NotificationKind::Warning(_) => Severity::Warning,
+            NotificationKind::Error(_) => Severity::Error,
+        }
+    }
 }
 
 pub struct BottomBarComponent {
@@ -38,26 +81,32 @@ pub struct BottomBarComponent {
     prompt_info: Option<PromptInfo>,
     notification_info: Option<NotificationInfo>,
     rect: Rectangle,
+    // The name shown for buffers that aren't backed by a file.
+    scratch_name: String,
+    // In seconds.
+    notification_duration: f32,
 }
 
 struct StatusInfo {
     buffer_name: String,
+    modified: bool,
+    line_ending: LineEnding,
     cursor_line: usize,
     line_count: usize,
 }
 
 struct PromptInfo {
+    kind: PromptKind,
     message: String,
     input: String,
 }
 
 struct NotificationInfo {
     message: String,
+    severity: Severity,
     start: Instant,
 }
 
-const NOTIFICATION_DURATION: f32 = 1.0;
-
 impl BottomBarComponent {
     pub fn new(rect: Rectangle, context: &SharedContext) -> Self {
         Self {
@@ -66,13 +115,17 @@ impl BottomBarComponent {
                     .editor
                     .get_file_name()
                     .cloned()
-                    .unwrap_or("[Scratch]".into()),
-                cursor_line: context.editor.get_view_cursor().line + 1,
+                    .unwrap_or_else(|| context.config.scratch_name.clone()),
+                modified: context.editor.is_modified(),
+                line_ending: context.editor.get_line_ending(),
+                cursor_line: context.editor.get_buffer_cursor().line + 1,
                 line_count: context.editor.get_buffer_line_count(),
             },
             prompt_info: None,
             notification_info: None,
             rect,
+            scratch_name: context.config.scratch_name.clone(),
+            notification_duration: context.config.notification_duration,
         }
     }
 
@@ -85,17 +138,32 @@ impl BottomBarComponent {
 
             queue!(writer, MoveTo(self.rect.left, self.rect.top))?;
             queue!(writer, PrintStyledContent(status_bar.negative()))?;
-        } else if let Some(NotificationInfo { message, .. }) = &self.notification_info {
+        } else if let Some(NotificationInfo {
+            message, severity, ..
+        }) = &self.notification_info
+        {
             let width = self.rect.width() as usize;
             let status_bar = format!("{message:0$.0$}", width);
+            let status_bar = match severity {
+                Severity::Info => status_bar.negative(),
+                Severity::Warning => status_bar.black().on_yellow(),
+                Severity::Error => status_bar.white().on_red().bold(),
+            };
 
             queue!(writer, MoveTo(self.rect.left, self.rect.top))?;
-            queue!(writer, PrintStyledContent(status_bar.negative()))?;
+            queue!(writer, PrintStyledContent(status_bar))?;
         } else {
-            let left_part = format!("{:.20}", self.status_info.buffer_name);
+            let modified = if self.status_info.modified {
+                " (modified)"
+            } else {
+                ""
+            };
+            let left_part = format!("{:.20}{modified}", self.status_info.buffer_name);
             let right_part = format!(
-                "{}/{}",
-                self.status_info.cursor_line, self.status_info.line_count,
+                "{} {}/{}",
+                self.status_info.line_ending.name(),
+                self.status_info.cursor_line,
+                self.status_info.line_count,
             );
             let total_len = left_part.len() + right_part.len();
 
@@ -117,31 +185,72 @@ impl BottomBarComponent {
         use BottomBarMessage::*;
 
         if let Some(NotificationInfo { start, .. }) = &self.notification_info {
-            if start.elapsed().as_secs_f32() > NOTIFICATION_DURATION {
+            if start.elapsed().as_secs_f32() > self.notification_duration {
                 self.notification_info = None;
             }
         }
 
         match message {
             UpdateStatus(status) => {
+                self.status_info.modified = status.modified;
+                self.status_info.line_ending = status.line_ending;
                 self.status_info.cursor_line = status.cursor_line;
                 self.status_info.line_count = status.line_count;
-                self.status_info.buffer_name = status.file_name.unwrap_or("[Scratch]".into());
+                self.status_info.buffer_name = status
+                    .file_name
+                    .unwrap_or_else(|| self.scratch_name.clone());
             }
             DisplayPrompt(prompt_kind) => {
                 self.prompt_info = Some(PromptInfo::new(prompt_kind));
                 queue.push_front(Focus::BottomBar);
             }
             DisplayNotification(notification_kind) => {
-                self.notification_info = Some(NotificationInfo::new(notification_kind));
+                self.display_notification(notification_kind);
+            }
+            ClosePrompt => {
+                self.prompt_info = None;
+            }
+            Resize(rect) => {
+                self.rect = rect;
             }
         }
 
         Ok(())
     }
 
+    pub fn is_confirming_replace(&self) -> bool {
+        matches!(
+            self.prompt_info,
+            Some(PromptInfo {
+                kind: PromptKind::ConfirmReplace,
+                ..
+            })
+        )
+    }
+
+    pub fn is_searching(&self) -> bool {
+        matches!(
+            self.prompt_info,
+            Some(PromptInfo {
+                kind: PromptKind::Search,
+                ..
+            })
+        )
+    }
+
+    pub fn display_notification(&mut self, notification_kind: NotificationKind) {
+        self.notification_info = Some(NotificationInfo::new(notification_kind));
+    }
+
+    // The confirmation prompt leaves the cursor on the match in the text area.
     pub fn cursor(&self) -> Option<Cursor> {
-        if let Some(PromptInfo { message, input, .. }) = &self.prompt_info {
+        if let Some(PromptInfo {
+            kind: PromptKind::ConfirmReplace,
+            ..
+        }) = &self.prompt_info
+        {
+            None
+        } else if let Some(PromptInfo { message, input, .. }) = &self.prompt_info {
             let message = format!("{message} {input}");
 
             let width = self.rect.width() as usize;
@@ -153,25 +262,93 @@ impl BottomBarComponent {
         }
     }
 
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        self.rect.contains(x, y)
+    }
+
+    // Clicking a notification dismisses it, bringing back what it covered.
+    pub fn process_mouse_event(&mut self, event: MouseEvent) {
+        if let MouseEventKind::Down(MouseButton::Left) = event.kind {
+            self.notification_info = None;
+        }
+    }
+
     pub fn process_event(&mut self, event: KeyEvent, queue: &mut MessageQueue) -> Result<()> {
-        if let Some(PromptInfo { input, .. }) = &mut self.prompt_info {
+        if let Some(PromptInfo { kind, input, .. }) = &mut self.prompt_info {
+            use EditorControllerMessage::*;
             use KeyCode::*;
             use KeyModifiers as KM;
 
             let KeyEvent { code, modifiers } = event;
             match (modifiers, code) {
-                (KM::NONE, Char(c)) => {
+                (KM::NONE, Char(c)) if matches!(kind, PromptKind::ConfirmReplace) => match c {
+                    'y' => queue.push_front(ReplaceMatch),
+                    'n' => queue.push_front(SkipMatch),
+                    'a' => queue.push_front(ReplaceAll),
+                    'q' => queue.push_front(EndReplace),
+                    _ => {}
+                },
+                (_, Char(_) | Backspace | Enter) if matches!(kind, PromptKind::ConfirmReplace) => {}
+
+                (KM::NONE | KM::SHIFT, Char(c)) => {
                     input.push(c);
+                    if let PromptKind::Search = kind {
+                        queue.push_front(Search(input.clone()));
+                    }
                 }
 
                 (KM::NONE, Backspace) => {
                     input.pop();
+                    if let PromptKind::Search = kind {
+                        queue.push_front(Search(input.clone()));
+                    }
                 }
+
+                (KM::NONE, Up | Left) if matches!(kind, PromptKind::Search) => {
+                    queue.push_front(SearchNext(input.clone(), SearchDirection::Backward));
+                }
+                (KM::NONE, Down | Right) if matches!(kind, PromptKind::Search) => {
+                    queue.push_front(SearchNext(input.clone(), SearchDirection::Forward));
+                }
+
                 (KM::NONE, Enter) => {
                     let prompt_info = self.prompt_info.take().unwrap();
 
+                    match prompt_info.kind {
+                        PromptKind::SaveAs => {
+                            queue.push_front(Focus::TextArea);
+                            queue.push_front(SaveAs(prompt_info.input));
+                        }
+                        PromptKind::Search => {
+                            queue.push_front(Focus::TextArea);
+                            queue.push_front(EndSearch);
+                        }
+                        PromptKind::GoToLine => {
+                            queue.push_front(Focus::TextArea);
+                            queue.push_front(GoToLine(prompt_info.input));
+                        }
+                        PromptKind::ReplacePattern => {
+                            queue.push_front(BottomBarMessage::DisplayPrompt(
+                                PromptKind::Replacement(prompt_info.input),
+                            ));
+                        }
+                        // The editor controller follows up with either the
+                        // confirmation prompt or a notification.
+                        PromptKind::Replacement(pattern) => {
+                            queue.push_front(StartReplace(pattern, prompt_info.input));
+                        }
+                        PromptKind::ConfirmReplace => unreachable!(),
+                    }
+                }
+                (KM::NONE, Esc) => {
+                    let prompt_info = self.prompt_info.take().unwrap();
+
                     queue.push_front(Focus::TextArea);
-                    queue.push_front(EditorControllerMessage::SaveAs(prompt_info.input));
+                    match prompt_info.kind {
+                        PromptKind::Search => queue.push_front(CancelSearch),
+                        PromptKind::ConfirmReplace => queue.push_front(EndReplace),
+                        _ => {}
+                    }
                 }
 
                 _ => {}
@@ -187,7 +364,15 @@ impl PromptInfo {
         Self {
             message: match prompt_kind {
                 PromptKind::SaveAs => "[Save As] Enter file path:".into(),
+                PromptKind::Search => "[Search] (Arrows to cycle, Esc to cancel):".into(),
+                PromptKind::ReplacePattern => "[Replace] Enter regex:".into(),
+                PromptKind::Replacement(_) => "[Replace] Enter replacement ($1 for groups):".into(),
+                PromptKind::GoToLine => "[Go To] Line (120, 120:15, +10, -10, 50%):".into(),
+                PromptKind::ConfirmReplace => {
+                    "[Replace] Replace this match? (y)es (n)o (a)ll (q)uit:".into()
+                }
             },
+            kind: prompt_kind,
             input: String::new(),
         }
     }
@@ -196,8 +381,21 @@ impl PromptInfo {
 impl NotificationInfo {
     fn new(notification_kind: NotificationKind) -> Self {
         Self {
+            severity: notification_kind.severity(),
             message: match notification_kind {
                 NotificationKind::SaveSuccess => "[Success] The buffer has been saved".into(),
+                NotificationKind::ReplaceSuccess { count } => {
+                    format!("[Success] Replaced {count} occurrence(s)")
+                }
+                NotificationKind::UnsavedChanges {
+                    remaining_presses,
+                    quit_keys,
+                } => format!(
+                    "[Warning] The buffer has unsaved changes. \
+                     Press {quit_keys} {remaining_presses} more time(s) to quit"
+                ),
+                NotificationKind::Warning(warning) => format!("[Warning] {warning}"),
+                NotificationKind::Error(error) => format!("[Error] {error}"),
             },
             start: Instant::now(),
         }
@@ -0,0 +1,159 @@
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::bottom_bar::{BottomBarMessage, NotificationKind};
+use crate::runner::MessageQueue;
+
+pub enum ClipboardMessage {
+    Publish(String),
+}
+
+pub trait ClipboardProvider {
+    // Puts `text` on the system clipboard. Providers that go through the
+    // terminal write their escape sequences to `terminal`.
+    fn copy(&mut self, text: &str, terminal: &mut dyn Write) -> Result<()>;
+}
+
+// Asks the terminal to set the clipboard, which also works over SSH, since the
+// clipboard that gets set is the one of the machine the terminal runs on. Not
+// every terminal supports it, and those that don't ignore the sequence.
+pub struct Osc52Provider;
+
+impl ClipboardProvider for Osc52Provider {
+    fn copy(&mut self, text: &str, terminal: &mut dyn Write) -> Result<()> {
+        write!(terminal, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+        Ok(())
+    }
+}
+
+// Pipes the text into a clipboard tool of the local desktop.
+pub struct CommandProvider {
+    program: &'static str,
+    args: &'static [&'static str],
+}
+
+// In order of preference, along with the environment variable that tells
+// whether the session the tool talks to is around.
+const CLIPBOARD_TOOLS: &[(&str, &[&str], Option<&str>)] = &[
+    ("wl-copy", &[], Some("WAYLAND_DISPLAY")),
+    ("xclip", &["-selection", "clipboard"], Some("DISPLAY")),
+    ("pbcopy", &[], None),
+];
+
+// How long copying waits for the tool to finish. Most tools either finish
+// right away or fork into the background, so this is only reached by ones that
+// hang, which then get left to finish on their own instead of freezing the
+// editor. Failures after that don't get reported.
+const COPY_TIMEOUT: Duration = Duration::from_millis(200);
+
+impl CommandProvider {
+    pub fn detect() -> Option<Self> {
+        CLIPBOARD_TOOLS
+            .iter()
+            .find(|(program, _, session)| {
+                session.is_none_or(|session| env::var_os(session).is_some()) && is_in_path(program)
+            })
+            .map(|&(program, args, _)| Self { program, args })
+    }
+}
+
+impl ClipboardProvider for CommandProvider {
+    fn copy(&mut self, text: &str, _terminal: &mut dyn Write) -> Result<()> {
+        // The tool's output would end up all over the screen.
+        let mut child = Command::new(self.program)
+            .args(self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.program))?;
+
+        // The tool gets fed and waited for on a thread of its own. Writing
+        // can block too, if the tool doesn't read the text. The thread waits
+        // for the tool in any case, so that it isn't left behind as a zombie.
+        let mut stdin = child.stdin.take().unwrap();
+        let text = text.to_owned();
+        let program = self.program;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // Dropping stdin closes it, which lets the tool know the text is
+            // over.
+            let written = stdin.write_all(text.as_bytes());
+            drop(stdin);
+            if written.is_err() {
+                let _ = child.kill();
+            }
+
+            // A tool that exits early breaks the pipe, and its exit status
+            // says more about why than that.
+            let result = match child.wait() {
+                Ok(status) if status.code().is_some_and(|code| code != 0) => {
+                    Err(anyhow!("{program} failed with {status}"))
+                }
+                Ok(_) => written.map_err(|error| error.into()),
+                Err(error) => Err(error.into()),
+            };
+            let _ = sender.send(result);
+        });
+
+        receiver.recv_timeout(COPY_TIMEOUT).unwrap_or(Ok(()))
+    }
+}
+
+fn is_in_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| {
+        env::split_paths(&path).any(|dir| Path::new(&dir).join(program).is_file())
+    })
+}
+
+// Clipboard tools would set the clipboard of the remote machine in an SSH
+// session, so the terminal gets asked instead.
+pub fn detect_provider() -> Box<dyn ClipboardProvider> {
+    let is_remote = env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some();
+    match CommandProvider::detect() {
+        Some(provider) if !is_remote => Box::new(provider),
+        _ => Box::new(Osc52Provider),
+    }
+}
+
+pub struct ClipboardComponent {
+    provider: Box<dyn ClipboardProvider>,
+    // Escape sequences waiting to be written along with the next frame.
+    pending: Vec<u8>,
+}
+
+impl ClipboardComponent {
+    pub fn new(provider: Box<dyn ClipboardProvider>) -> Self {
+        Self {
+            provider,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn render(&mut self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.pending)?;
+        self.pending.clear();
+
+        Ok(())
+    }
+
+    pub fn update(&mut self, message: ClipboardMessage, queue: &mut MessageQueue) -> Result<()> {
+        let ClipboardMessage::Publish(text) = message;
+
+        if let Err(error) = self.provider.copy(&text, &mut self.pending) {
+            queue.push_front(BottomBarMessage::DisplayNotification(
+                NotificationKind::Error(format!("The clipboard could not be set: {error}")),
+            ));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use crossterm::style::Color;
+use serde::{Deserialize, Deserializer};
+
+use kilo_rs_backend::core::WordClasses;
+use kilo_rs_backend::save::SaveOptions;
+use kilo_rs_backend::view::rendering::DEFAULT_TAB_WIDTH;
+use kilo_rs_backend::view::{LineNumbers, Scrolloff, Wrap};
+
+// The settings from the config file, with every key that's left out falling
+// back to its default. Unknown keys are errors rather than being ignored, so
+// that typos don't go unnoticed.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub tab_width: usize,
+    // Whether indenting inserts spaces instead of tabs.
+    pub expand_tab: bool,
+    pub scrolloff: ScrolloffConfig,
+    pub line_numbers: LineNumbersConfig,
+    pub wrap: WrapConfig,
+    pub auto_indent: bool,
+    pub theme: Theme,
+    // In seconds.
+    pub notification_duration: f32,
+    // Chars that are part of words, besides letters and digits.
+    pub word_chars: String,
+    pub backup: bool,
+    pub scratch_name: String,
+    // Key bindings on top of the default ones, from keys like "ctrl+k ctrl+u"
+    // to command names, or to "none" to unbind the keys.
+    pub keys: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrolloffConfig {
+    pub vertical: usize,
+    pub horizontal: usize,
+}
+
+// The backend doesn't know about serde, so its settings are mirrored here.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineNumbersConfig {
+    #[default]
+    Off,
+    Absolute,
+    Relative,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapConfig {
+    #[default]
+    Off,
+    Chars,
+    Words,
+}
+
+// The colors of the token classes and the gutter. Colors are either one of the
+// names crossterm knows (like "dark_cyan") or a hex code like "#ff8800".
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    #[serde(deserialize_with = "deserialize_color")]
+    pub keyword: Color,
+    #[serde(rename = "type", deserialize_with = "deserialize_color")]
+    pub type_: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub string: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub comment: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub number: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub line_number: Color,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub current_line_number: Color,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            expand_tab: false,
+            scrolloff: ScrolloffConfig::default(),
+            line_numbers: LineNumbersConfig::default(),
+            wrap: WrapConfig::default(),
+            auto_indent: true,
+            theme: Theme::default(),
+            notification_duration: 1.0,
+            word_chars: WordClasses::default().word_chars,
+            backup: false,
+            scratch_name: "[Scratch]".into(),
+            keys: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            keyword: Color::Yellow,
+            type_: Color::Green,
+            string: Color::Magenta,
+            comment: Color::Cyan,
+            number: Color::Red,
+            line_number: Color::DarkGrey,
+            current_line_number: Color::White,
+        }
+    }
+}
+
+impl Config {
+    // Reads the config file from the user's config directory. Not having one
+    // isn't an error, the defaults are used then.
+    pub fn load() -> Result<Self> {
+        match config_path() {
+            Some(path) => Self::from_file(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => bail!("{}: {error}", path.display()),
+        };
+
+        // The whole message of a TOML error spans several lines, which don't
+        // fit into the bottom bar.
+        let config: Self = toml::from_str(&source).map_err(|error| {
+            let line = error
+                .span()
+                .map(|span| source[..span.start].matches('\n').count() + 1);
+            match line {
+                Some(line) => anyhow!("{}:{line}: {}", path.display(), error.message()),
+                None => anyhow!("{}: {}", path.display(), error.message()),
+            }
+        })?;
+
+        if config.tab_width == 0 {
+            bail!("{}: tab_width must be at least 1", path.display());
+        }
+        if config.notification_duration < 0.0 {
+            bail!(
+                "{}: notification_duration can't be negative",
+                path.display()
+            );
+        }
+
+        Ok(config)
+    }
+
+    pub fn scrolloff(&self) -> Scrolloff {
+        Scrolloff {
+            vertical: self.scrolloff.vertical,
+            horizontal: self.scrolloff.horizontal,
+        }
+    }
+
+    pub fn line_numbers(&self) -> LineNumbers {
+        match self.line_numbers {
+            LineNumbersConfig::Off => LineNumbers::Off,
+            LineNumbersConfig::Absolute => LineNumbers::Absolute,
+            LineNumbersConfig::Relative => LineNumbers::Relative,
+        }
+    }
+
+    pub fn wrap(&self) -> Wrap {
+        match self.wrap {
+            WrapConfig::Off => Wrap::Off,
+            WrapConfig::Chars => Wrap::Chars,
+            WrapConfig::Words => Wrap::Words,
+        }
+    }
+
+    pub fn word_classes(&self) -> WordClasses {
+        WordClasses {
+            word_chars: self.word_chars.clone(),
+        }
+    }
+
+    pub fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            backup: self.backup,
+        }
+    }
+}
+
+// `~/.config/kilo-rs/config.toml` on Linux.
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("kilo-rs").join("config.toml"))
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_color(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown color `{name}`")))
+}
+
+fn parse_color(name: &str) -> Option<Color> {
+    match name.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 => {
+            let channel = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+            Some(Color::Rgb {
+                r: channel(0)?,
+                g: channel(2)?,
+                b: channel(4)?,
+            })
+        }
+        Some(_) => None,
+        None => Color::try_from(name).ok(),
+    }
+}
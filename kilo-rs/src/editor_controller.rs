@@ -1,21 +1,30 @@
 use anyhow::Result;
+use kilo_rs_backend::core::{LineEnding, Location, SearchDirection};
 use kilo_rs_backend::editor::Editor;
+use kilo_rs_backend::view::{LineNumbers, Wrap};
 
 use crate::{
-    bottom_bar::{self, BottomBarMessage, NotificationKind},
+    app::Focus,
+    bottom_bar::{self, BottomBarMessage, NotificationKind, PromptKind},
+    clipboard::ClipboardMessage,
     runner::MessageQueue,
     shared::SharedContext,
     text_area::{self, TextAreaMessage},
 };
 
+#[derive(Default)]
 pub struct EditorControllerComponent;
 
+#[derive(Clone)]
 pub enum EditorControllerMessage {
     MoveCursorUp,
     MoveCursorDown,
     MoveCursorLeft,
     MoveCursorRight,
 
+    MoveCursorWordLeft,
+    MoveCursorWordRight,
+
     MoveCursorToLineStart,
     MoveCursorToLineEnd,
 
@@ -25,13 +34,66 @@ pub enum EditorControllerMessage {
     MoveCursorToBufferTop,
     MoveCursorToBufferBottom,
 
+    // Locations in the text area, like where it got clicked.
+    MoveCursorToViewLocation(Location),
+    SelectToViewLocation(Location),
+
+    // By this many screen rows, down for positive ones.
+    ScrollView(isize),
+
+    // Takes the go-to prompt's input as is.
+    GoToLine(String),
+
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    SelectWordLeft,
+    SelectWordRight,
+    SelectToLineStart,
+    SelectToLineEnd,
+
+    Cut,
+    Copy,
+    Paste,
+    PasteOlder,
+
     RemoveCharBehind,
     RemoveCharInFront,
+    RemoveWordBehind,
+    RemoveWordInFront,
 
     InsertChar(char),
     InsertLine,
+    Indent,
+    Dedent,
+
+    Undo,
+    Redo,
+
+    ToggleLineEnding,
+    ToggleWrap,
+    ToggleAutoIndent,
+    ToggleLineNumbers,
+
+    StartSearch,
+    Search(String),
+    SearchNext(String, SearchDirection),
+    EndSearch,
+    CancelSearch,
 
+    // Pattern and replacement.
+    StartReplace(String, String),
+    ReplaceMatch,
+    SkipMatch,
+    ReplaceAll,
+    EndReplace,
+
+    Save,
     SaveAs(String),
+
+    // The new size of the text area, after the terminal got resized.
+    Resize { width: usize, height: usize },
 }
 
 impl EditorControllerComponent {
@@ -47,42 +109,283 @@ impl EditorControllerComponent {
     ) -> Result<()> {
         use EditorControllerMessage::*;
 
-        if let SaveAs(path) = message {
-            context.editor.save_file_as(&path).unwrap();
-
-            queue.push_front(SAVE_NOTIFICATION_MESSAGE);
-            queue.push_front(make_update_bottom_bar_message(&context.editor));
-        } else {
-            match message {
-                MoveCursorUp => context.editor.move_cursor_up(),
-                MoveCursorDown => context.editor.move_cursor_down(),
-                MoveCursorLeft => context.editor.move_cursor_left(),
-                MoveCursorRight => context.editor.move_cursor_right(),
-                MoveCursorToLineStart => context.editor.move_cursor_to_line_start(),
-                MoveCursorToLineEnd => context.editor.move_cursor_to_line_end(),
-                MoveOneViewUp => context.editor.move_one_view_up(),
-                MoveOneViewDown => context.editor.move_one_view_down(),
-                MoveCursorToBufferTop => context.editor.move_cursor_to_buffer_top(),
-                MoveCursorToBufferBottom => context.editor.move_cursor_to_buffer_bottom(),
-                RemoveCharBehind => context.editor.remove_char_behind(),
-                RemoveCharInFront => context.editor.remove_char_in_front(),
-                InsertChar(c) => context.editor.insert_char(c.clone()),
-                InsertLine => context.editor.insert_line(),
-                SaveAs(_) => unreachable!(),
-            };
-
-            queue.push_front(make_update_text_area_message(&context.editor));
-            queue.push_front(make_update_bottom_bar_message(&context.editor));
+        // Moving the cursor without extending the selection drops it.
+        if message.is_motion() {
+            context.editor.clear_selection();
         }
 
+        match message {
+            Save => {
+                // A scratch buffer has nowhere to be saved to yet, so ask for a path.
+                if context.editor.get_file_name().is_none() {
+                    queue.push_front(BottomBarMessage::DisplayPrompt(PromptKind::SaveAs));
+                } else {
+                    let result = context.editor.save_file();
+
+                    queue.push_front(make_save_notification_message(result));
+                    queue.push_front(make_update_bottom_bar_message(&context.editor));
+                }
+                return Ok(());
+            }
+            SaveAs(path) => {
+                let result = context.editor.save_file_as(&path);
+
+                queue.push_front(make_save_notification_message(result));
+                queue.push_front(make_update_bottom_bar_message(&context.editor));
+                return Ok(());
+            }
+
+            StartReplace(pattern, replacement) => {
+                match context.editor.start_replace(&pattern, &replacement) {
+                    Ok(true) => queue
+                        .push_front(BottomBarMessage::DisplayPrompt(PromptKind::ConfirmReplace)),
+                    Ok(false) => finish_replace(queue, context),
+                    Err(error) => {
+                        queue.push_front(Focus::TextArea);
+                        queue.push_front(BottomBarMessage::DisplayNotification(
+                            NotificationKind::Error(format!("Invalid regex: {error}")),
+                        ));
+                    }
+                }
+            }
+            ReplaceMatch => {
+                if !context.editor.replace_match() {
+                    finish_replace(queue, context);
+                }
+            }
+            SkipMatch => {
+                if !context.editor.skip_match() {
+                    finish_replace(queue, context);
+                }
+            }
+            ReplaceAll => {
+                context.editor.replace_all();
+                finish_replace(queue, context);
+            }
+            EndReplace => finish_replace(queue, context),
+
+            MoveCursorUp => context.editor.move_cursor_up(),
+            MoveCursorDown => context.editor.move_cursor_down(),
+            MoveCursorLeft => context.editor.move_cursor_left(),
+            MoveCursorRight => context.editor.move_cursor_right(),
+            MoveCursorWordLeft => context.editor.move_cursor_word_left(),
+            MoveCursorWordRight => context.editor.move_cursor_word_right(),
+            MoveCursorToLineStart => context.editor.move_cursor_to_line_start(),
+            MoveCursorToLineEnd => context.editor.move_cursor_to_line_end(),
+            MoveOneViewUp => context.editor.move_one_view_up(),
+            MoveOneViewDown => context.editor.move_one_view_down(),
+            MoveCursorToBufferTop => context.editor.move_cursor_to_buffer_top(),
+            MoveCursorToBufferBottom => context.editor.move_cursor_to_buffer_bottom(),
+            MoveCursorToViewLocation(location) => {
+                context.editor.move_cursor_to_view_location(location)
+            }
+            SelectToViewLocation(location) => context
+                .editor
+                .extend_selection(|editor| editor.move_cursor_to_view_location(location)),
+            ScrollView(rows) => context.editor.scroll_view(rows),
+            Resize { width, height } => context.editor.set_size(width, height),
+            GoToLine(input) => match parse_go_to_target(&input, &context.editor) {
+                Some(location) => context.editor.move_cursor_to(location),
+                None => queue.push_front(BottomBarMessage::DisplayNotification(
+                    NotificationKind::Error(format!("Invalid line: {input}")),
+                )),
+            },
+            SelectUp => context.editor.extend_selection(Editor::move_cursor_up),
+            SelectDown => context.editor.extend_selection(Editor::move_cursor_down),
+            SelectLeft => context.editor.extend_selection(Editor::move_cursor_left),
+            SelectRight => context.editor.extend_selection(Editor::move_cursor_right),
+            SelectWordLeft => context
+                .editor
+                .extend_selection(Editor::move_cursor_word_left),
+            SelectWordRight => context
+                .editor
+                .extend_selection(Editor::move_cursor_word_right),
+            SelectToLineStart => context
+                .editor
+                .extend_selection(Editor::move_cursor_to_line_start),
+            SelectToLineEnd => context
+                .editor
+                .extend_selection(Editor::move_cursor_to_line_end),
+            Cut => {
+                if let Some(text) = context.editor.cut_selection() {
+                    queue.push_front(ClipboardMessage::Publish(text));
+                }
+            }
+            Copy => {
+                if let Some(text) = context.editor.copy_selection() {
+                    queue.push_front(ClipboardMessage::Publish(text));
+                }
+            }
+            Paste => context.editor.paste(),
+            PasteOlder => context.editor.paste_older(),
+            RemoveCharBehind => context.editor.remove_char_behind(),
+            RemoveCharInFront => context.editor.remove_char_in_front(),
+            RemoveWordBehind => context.editor.remove_word_behind(),
+            RemoveWordInFront => context.editor.remove_word_in_front(),
+            InsertChar(c) => context.editor.insert_char(c),
+            InsertLine => context.editor.insert_line(),
+            Indent => context.editor.indent(),
+            Dedent => context.editor.dedent(),
+            Undo => context.editor.undo(),
+            Redo => context.editor.redo(),
+            ToggleLineEnding => {
+                let line_ending = match context.editor.get_line_ending() {
+                    LineEnding::Lf => LineEnding::CrLf,
+                    LineEnding::CrLf => LineEnding::Lf,
+                };
+                context.editor.set_line_ending(line_ending)
+            }
+            ToggleWrap => {
+                let wrap = match context.editor.get_wrap() {
+                    Wrap::Off => Wrap::Words,
+                    Wrap::Chars | Wrap::Words => Wrap::Off,
+                };
+                context.editor.set_wrap(wrap)
+            }
+            ToggleLineNumbers => {
+                let line_numbers = match context.editor.get_line_numbers() {
+                    LineNumbers::Off => LineNumbers::Absolute,
+                    LineNumbers::Absolute => LineNumbers::Relative,
+                    LineNumbers::Relative => LineNumbers::Off,
+                };
+                context.editor.set_line_numbers(line_numbers)
+            }
+            ToggleAutoIndent => {
+                let auto_indent = !context.editor.get_auto_indent();
+                context.editor.set_auto_indent(auto_indent)
+            }
+            StartSearch => context.editor.start_search(),
+            Search(query) => context.editor.search(&query),
+            SearchNext(query, direction) => context.editor.search_next(&query, direction),
+            EndSearch => context.editor.end_search(),
+            CancelSearch => context.editor.cancel_search(),
+        };
+
+        queue.push_front(make_update_text_area_message(&context.editor));
+        queue.push_front(make_update_bottom_bar_message(&context.editor));
+
         Ok(())
     }
 }
 
+impl EditorControllerMessage {
+    // The messages that can be bound to keys, by the names the keymap uses for
+    // them. The ones that take arguments, or only make sense while a prompt is
+    // open, aren't among them.
+    pub fn from_name(name: &str) -> Option<Self> {
+        use EditorControllerMessage::*;
+
+        Some(match name {
+            "move_cursor_up" => MoveCursorUp,
+            "move_cursor_down" => MoveCursorDown,
+            "move_cursor_left" => MoveCursorLeft,
+            "move_cursor_right" => MoveCursorRight,
+            "move_cursor_word_left" => MoveCursorWordLeft,
+            "move_cursor_word_right" => MoveCursorWordRight,
+            "move_cursor_to_line_start" => MoveCursorToLineStart,
+            "move_cursor_to_line_end" => MoveCursorToLineEnd,
+            "move_one_view_up" => MoveOneViewUp,
+            "move_one_view_down" => MoveOneViewDown,
+            "move_cursor_to_buffer_top" => MoveCursorToBufferTop,
+            "move_cursor_to_buffer_bottom" => MoveCursorToBufferBottom,
+            "select_up" => SelectUp,
+            "select_down" => SelectDown,
+            "select_left" => SelectLeft,
+            "select_right" => SelectRight,
+            "select_word_left" => SelectWordLeft,
+            "select_word_right" => SelectWordRight,
+            "select_to_line_start" => SelectToLineStart,
+            "select_to_line_end" => SelectToLineEnd,
+            "cut" => Cut,
+            "copy" => Copy,
+            "paste" => Paste,
+            "paste_older" => PasteOlder,
+            "remove_char_behind" => RemoveCharBehind,
+            "remove_char_in_front" => RemoveCharInFront,
+            "remove_word_behind" => RemoveWordBehind,
+            "remove_word_in_front" => RemoveWordInFront,
+            "insert_line" => InsertLine,
+            "indent" => Indent,
+            "dedent" => Dedent,
+            "undo" => Undo,
+            "redo" => Redo,
+            "toggle_line_ending" => ToggleLineEnding,
+            "toggle_wrap" => ToggleWrap,
+            "toggle_auto_indent" => ToggleAutoIndent,
+            "toggle_line_numbers" => ToggleLineNumbers,
+            _ => return None,
+        })
+    }
+
+    fn is_motion(&self) -> bool {
+        use EditorControllerMessage::*;
+
+        matches!(
+            self,
+            MoveCursorUp
+                | MoveCursorDown
+                | MoveCursorLeft
+                | MoveCursorRight
+                | MoveCursorWordLeft
+                | MoveCursorWordRight
+                | MoveCursorToLineStart
+                | MoveCursorToLineEnd
+                | MoveOneViewUp
+                | MoveOneViewDown
+                | MoveCursorToBufferTop
+                | MoveCursorToBufferBottom
+                | MoveCursorToViewLocation(_)
+        )
+    }
+}
+
+// Parses `LINE[:COL]`, where the line is either absolute (`120`), relative to
+// the cursor (`+10`, `-10`) or a percentage of the buffer (`50%`). Lines and
+// columns count from 1, like in the status bar.
+fn parse_go_to_target(input: &str, editor: &Editor) -> Option<Location> {
+    let input = input.trim();
+    let (line, col) = match input.split_once(':') {
+        Some((line, col)) => (line, Some(col.trim().parse::<usize>().ok()?)),
+        None => (input, None),
+    };
+
+    let cursor = editor.get_buffer_cursor();
+    let last_line = editor.get_buffer_line_count().saturating_sub(1);
+    let line = line.trim();
+    let line = if let Some(offset) = line.strip_prefix('+') {
+        cursor.line.saturating_add(offset.parse().ok()?)
+    } else if let Some(offset) = line.strip_prefix('-') {
+        cursor.line.saturating_sub(offset.parse().ok()?)
+    } else if let Some(percentage) = line.strip_suffix('%') {
+        let percentage = percentage.parse::<usize>().ok()?.min(100);
+        last_line * percentage / 100
+    } else {
+        line.parse::<usize>().ok()?.saturating_sub(1)
+    };
+
+    let col = match col {
+        Some(col) => col.saturating_sub(1),
+        None => 0,
+    };
+    Some(Location::new(line, col))
+}
+
+fn finish_replace(queue: &mut MessageQueue, context: &mut SharedContext) {
+    let count = context.editor.end_replace();
+
+    queue.push_front(Focus::TextArea);
+    queue.push_front(BottomBarMessage::ClosePrompt);
+    queue.push_front(BottomBarMessage::DisplayNotification(
+        NotificationKind::ReplaceSuccess { count },
+    ));
+}
+
 fn make_update_bottom_bar_message(editor: &Editor) -> BottomBarMessage {
     BottomBarMessage::UpdateStatus(bottom_bar::StatusUpdate {
         file_name: editor.get_file_name().cloned(),
-        cursor_line: editor.get_view_cursor().line.saturating_add(1),
+        modified: editor.is_modified(),
+        line_ending: editor.get_line_ending(),
+        cursor_line: editor.get_buffer_cursor().line.saturating_add(1),
         line_count: editor.get_buffer_line_count(),
     })
 }
@@ -94,5 +397,9 @@ fn make_update_text_area_message(editor: &Editor) -> TextAreaMessage {
     })
 }
 
-const SAVE_NOTIFICATION_MESSAGE: BottomBarMessage =
-    BottomBarMessage::DisplayNotification(NotificationKind::SaveSuccess);
+fn make_save_notification_message(result: Result<()>) -> BottomBarMessage {
+    BottomBarMessage::DisplayNotification(match result {
+        Ok(()) => NotificationKind::SaveSuccess,
+        Err(error) => NotificationKind::Error(format!("The buffer could not be saved: {error}")),
+    })
+}
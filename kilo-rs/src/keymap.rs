@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+
+use anyhow::{anyhow, bail, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::editor_controller::EditorControllerMessage;
+
+// What a key binding runs. The commands besides `Editor` are handled by the app
+// and work whichever component has the focus, while the editor controller
+// messages only get sent while the text area has it.
+#[derive(Clone)]
+pub enum Command {
+    Quit,
+    Save,
+    SaveAs,
+    Search,
+    GoToLine,
+    Replace,
+    Editor(EditorControllerMessage),
+}
+
+impl Command {
+    // Commands are named in snake case in the config file, like "save_as" or
+    // "move_cursor_word_left".
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "quit" => Command::Quit,
+            "save" => Command::Save,
+            "save_as" => Command::SaveAs,
+            "search" => Command::Search,
+            "go_to_line" => Command::GoToLine,
+            "replace" => Command::Replace,
+            _ => Command::Editor(EditorControllerMessage::from_name(name)?),
+        })
+    }
+
+    pub fn is_global(&self) -> bool {
+        !matches!(self, Command::Editor(_))
+    }
+}
+
+// A sequence of key presses. Most bindings are a single key, chords like
+// "ctrl+k ctrl+u" have more.
+type Keys = Vec<KeyEvent>;
+
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("ctrl+q", "quit"),
+    ("ctrl+s", "save"),
+    ("ctrl+alt+s", "save_as"),
+    ("ctrl+f", "search"),
+    ("ctrl+g", "go_to_line"),
+    ("ctrl+r", "replace"),
+    ("up", "move_cursor_up"),
+    ("down", "move_cursor_down"),
+    ("left", "move_cursor_left"),
+    ("right", "move_cursor_right"),
+    ("ctrl+left", "move_cursor_word_left"),
+    ("ctrl+right", "move_cursor_word_right"),
+    ("home", "move_cursor_to_line_start"),
+    ("end", "move_cursor_to_line_end"),
+    ("pageup", "move_one_view_up"),
+    ("pagedown", "move_one_view_down"),
+    ("ctrl+pageup", "move_cursor_to_buffer_top"),
+    ("ctrl+pagedown", "move_cursor_to_buffer_bottom"),
+    ("shift+up", "select_up"),
+    ("shift+down", "select_down"),
+    ("shift+left", "select_left"),
+    ("shift+right", "select_right"),
+    ("ctrl+shift+left", "select_word_left"),
+    ("ctrl+shift+right", "select_word_right"),
+    ("shift+home", "select_to_line_start"),
+    ("shift+end", "select_to_line_end"),
+    ("ctrl+x", "cut"),
+    ("ctrl+c", "copy"),
+    ("ctrl+v", "paste"),
+    ("alt+v", "paste_older"),
+    ("backspace", "remove_char_behind"),
+    ("delete", "remove_char_in_front"),
+    ("ctrl+backspace", "remove_word_behind"),
+    // Most terminals send Ctrl+H for Ctrl+Backspace.
+    ("ctrl+h", "remove_word_behind"),
+    ("ctrl+delete", "remove_word_in_front"),
+    ("enter", "insert_line"),
+    ("tab", "indent"),
+    ("shift+tab", "dedent"),
+    ("ctrl+z", "undo"),
+    ("ctrl+y", "redo"),
+    ("ctrl+alt+l", "toggle_line_ending"),
+    ("alt+z", "toggle_wrap"),
+    ("alt+i", "toggle_auto_indent"),
+    ("alt+n", "toggle_line_numbers"),
+];
+
+// Binding keys to this in the config file removes their default binding.
+const UNBOUND: &str = "none";
+
+pub struct Keymap {
+    // Each command is kept along with its keys as they were written, to name
+    // them in messages.
+    bindings: HashMap<Keys, (Command, String)>,
+    // The keys of the chord that is being typed.
+    pending: Keys,
+}
+
+pub enum KeyLookup {
+    // The command, and the keys bound to it as they were written.
+    Command(Command, String),
+    // The keys so far are the start of a chord.
+    Pending,
+    // The keys don't make up any binding. A single key gets handled by the
+    // component with the focus. Of a longer sequence, only the last key is
+    // looked up again, on its own.
+    Unbound(Keys),
+}
+
+impl Keymap {
+    // Applies the bindings from the config file on top of the default ones.
+    // Bindings that can't be used are left out, and the reasons why get
+    // returned along with the keymap.
+    pub fn new(overrides: &BTreeMap<String, String>) -> (Self, Vec<String>) {
+        let mut problems = Vec::new();
+
+        let mut bindings: Vec<(String, Keys, Command, bool)> = DEFAULT_BINDINGS
+            .iter()
+            .map(|&(keys, name)| {
+                let command = Command::from_name(name).expect("unknown default command");
+                let parsed = parse_keys(keys).expect("invalid default keys");
+                (keys.to_string(), parsed, command, false)
+            })
+            .collect();
+
+        let mut overridden = HashMap::new();
+        for (keys, name) in overrides {
+            let parsed = match parse_keys(keys) {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    problems.push(error.to_string());
+                    continue;
+                }
+            };
+            if let Some(other) = overridden.insert(parsed.clone(), keys) {
+                problems.push(format!("`{other}` and `{keys}` are the same keys"));
+                continue;
+            }
+
+            bindings.retain(|(_, other, _, _)| *other != parsed);
+            if name == UNBOUND {
+                continue;
+            }
+            match Command::from_name(name) {
+                Some(command) => bindings.push((keys.clone(), parsed, command, true)),
+                None => problems.push(format!("Unknown command `{name}` for `{keys}`")),
+            }
+        }
+
+        // A binding that starts another one would make the longer one
+        // unreachable. The user's bindings win over the default ones, and the
+        // shorter of two user bindings wins.
+        let conflicts: Vec<(usize, usize)> = (0..bindings.len())
+            .flat_map(|i| (0..bindings.len()).map(move |j| (i, j)))
+            .filter(|&(i, j)| {
+                let (shorter, longer) = (&bindings[i].1, &bindings[j].1);
+                shorter.len() < longer.len() && longer.starts_with(shorter)
+            })
+            .collect();
+        let mut dropped = vec![false; bindings.len()];
+        for (i, j) in conflicts {
+            let (shorter, longer) = (&bindings[i], &bindings[j]);
+            if shorter.3 || longer.3 {
+                problems.push(format!("`{}` conflicts with `{}`", shorter.0, longer.0));
+            }
+            if !shorter.3 && longer.3 {
+                dropped[i] = true;
+            } else {
+                dropped[j] = true;
+            }
+        }
+
+        let bindings = bindings
+            .into_iter()
+            .zip(dropped)
+            .filter(|(_, dropped)| !dropped)
+            .map(|((name, keys, command, _), _)| (keys, (command, name)))
+            .collect();
+        let keymap = Self {
+            bindings,
+            pending: Vec::new(),
+        };
+        (keymap, problems)
+    }
+
+    // Feeds a key press into the keymap. Only bindings to commands `is_active`
+    // accepts are taken into account.
+    pub fn process_key(
+        &mut self,
+        event: KeyEvent,
+        is_active: impl Fn(&Command) -> bool,
+    ) -> KeyLookup {
+        self.pending.push(event);
+
+        let binding = self.bindings.get(&self.pending);
+        if let Some((command, name)) = binding.filter(|(command, _)| is_active(command)) {
+            self.pending.clear();
+            return KeyLookup::Command(command.clone(), name.clone());
+        }
+
+        let is_chord_start = self.bindings.iter().any(|(keys, (command, _))| {
+            keys.len() > self.pending.len() && keys.starts_with(&self.pending) && is_active(command)
+        });
+        if is_chord_start {
+            KeyLookup::Pending
+        } else {
+            KeyLookup::Unbound(mem::take(&mut self.pending))
+        }
+    }
+}
+
+// Parses whitespace separated keys, each of them optionally prefixed with
+// modifiers, like "ctrl+shift+left" or "ctrl+k ctrl+u".
+fn parse_keys(text: &str) -> Result<Keys> {
+    let keys = text
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<Keys>>()
+        .map_err(|error| anyhow!("Invalid keys `{text}`: {error}"))?;
+    if keys.is_empty() {
+        bail!("Invalid keys `{text}`: there are none");
+    }
+    Ok(keys)
+}
+
+fn parse_key(text: &str) -> Result<KeyEvent> {
+    // The key itself can be a "+" too, as in "ctrl++".
+    let (modifiers, key) = match text.strip_suffix("++") {
+        Some(modifiers) => (modifiers, "+"),
+        None => text.rsplit_once('+').unwrap_or(("", text)),
+    };
+
+    let mut modifiers = modifiers
+        .split('+')
+        .filter(|modifier| !modifier.is_empty())
+        .map(|modifier| match modifier.to_lowercase().as_str() {
+            "ctrl" | "control" => Ok(KeyModifiers::CONTROL),
+            "alt" => Ok(KeyModifiers::ALT),
+            "shift" => Ok(KeyModifiers::SHIFT),
+            _ => Err(anyhow!("unknown modifier `{modifier}`")),
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .fold(KeyModifiers::NONE, |all, modifier| all | modifier);
+
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match key.to_lowercase().as_str() {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "esc" => KeyCode::Esc,
+            "space" => KeyCode::Char(' '),
+            name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => bail!("unknown key `{key}`"),
+            },
+        },
+    };
+
+    // Terminals send letters typed with Shift as uppercase ones, and Shift+Tab
+    // as a key of its own (with Shift still held down).
+    let code = match code {
+        KeyCode::Char(c) if c.is_uppercase() => {
+            modifiers |= KeyModifiers::SHIFT;
+            code
+        }
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        KeyCode::BackTab => {
+            modifiers |= KeyModifiers::SHIFT;
+            code
+        }
+        _ => code,
+    };
+    Ok(KeyEvent::new(code, modifiers))
+}
@@ -1,6 +1,9 @@
 pub mod app;
 pub mod bottom_bar;
+pub mod clipboard;
+pub mod config;
 pub mod editor_controller;
+pub mod keymap;
 pub mod runner;
 pub mod shared;
 pub mod term_utils;
@@ -22,6 +22,7 @@ pub enum ShouldQuit {
     No,
 }
 
+#[derive(Default)]
 pub struct MessageQueue(VecDeque<AppMessage>);
 
 impl MessageQueue {
@@ -90,10 +91,19 @@ impl AppRunner {
     }
 
     fn process_events(&mut self) -> Result<ShouldQuit> {
-        if let Event::Key(event) = event::read()? {
-            self.app.process_event(event, &mut self.queue)
-        } else {
-            Ok(ShouldQuit::No)
+        match event::read()? {
+            Event::Key(event) => self.app.process_event(event, &mut self.queue),
+            Event::Mouse(event) => {
+                self.app.process_mouse_event(event, &mut self.queue)?;
+                Ok(ShouldQuit::No)
+            }
+            // Whatever was on the screen is laid out for the old size, so
+            // it all gets drawn again from scratch.
+            Event::Resize(width, height) => {
+                self.app.process_resize(width, height, &mut self.queue);
+                queue!(self.stdout, Clear(All))?;
+                Ok(ShouldQuit::No)
+            }
         }
     }
 }
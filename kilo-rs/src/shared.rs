@@ -1,7 +1,10 @@
 use kilo_rs_backend::editor::Editor;
 
+use crate::config::Config;
+
 pub struct SharedContext {
     pub editor: Editor,
+    pub config: Config,
 }
 
 pub struct Rectangle {
@@ -28,4 +31,8 @@ impl Rectangle {
     pub fn height(&self) -> u16 {
         self.bottom - self.top + 1
     }
+
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        (self.left..=self.right).contains(&x) && (self.top..=self.bottom).contains(&y)
+    }
 }
@@ -1,17 +1,24 @@
+use std::io;
+
 use anyhow::Result;
-use crossterm::terminal;
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::{execute, terminal};
 
+// Mouse capture gets turned on and off along with raw mode, so that the
+// terminal is left the way it was found either way.
 pub struct RawModeOverride;
 
 impl RawModeOverride {
     pub fn new() -> Result<Self> {
         terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnableMouseCapture)?;
         Ok(Self)
     }
 }
 
 impl Drop for RawModeOverride {
     fn drop(&mut self) {
+        execute!(io::stdout(), DisableMouseCapture).unwrap();
         terminal::disable_raw_mode().unwrap();
     }
 }
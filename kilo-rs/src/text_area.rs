@@ -3,31 +3,38 @@ use std::io::Write;
 use anyhow::Result;
 
 use crossterm::cursor::MoveTo;
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
 use crossterm::queue;
-use crossterm::style::Print;
+use crossterm::style::{Color, PrintStyledContent, Stylize};
 use crossterm::terminal::{Clear, ClearType::UntilNewLine};
 
 use kilo_rs_backend::core::Location;
 use kilo_rs_backend::editor::Editor;
+use kilo_rs_backend::view::highlighting::TokenClass;
+use kilo_rs_backend::view::rendering::StyledLine;
 
+use crate::config::Theme;
 use crate::editor_controller::EditorControllerMessage;
 use crate::runner::MessageQueue;
 use crate::shared::SharedContext;
 use crate::term_utils::Cursor;
 
+// How far one notch of the scroll wheel scrolls.
+const SCROLL_ROWS: isize = 3;
+
 pub enum TextAreaMessage {
     Update(UpdateMessage),
 }
 
 pub struct UpdateMessage {
-    pub lines: Box<dyn Iterator<Item = String> + Send>,
+    pub lines: Box<dyn Iterator<Item = StyledLine> + Send>,
     pub cursor: kilo_rs_backend::core::Location,
 }
 
 pub struct TextAreaComponent {
-    lines: Vec<String>,
+    lines: Vec<StyledLine>,
     cursor: Cursor,
+    theme: Theme,
 }
 
 impl TextAreaComponent {
@@ -35,16 +42,30 @@ impl TextAreaComponent {
         Self {
             lines: get_editor_lines(&context.editor),
             cursor: get_editor_cursor(&context.editor),
+            theme: context.config.theme.clone(),
         }
     }
 
     pub fn render(&self, writer: &mut impl Write) -> Result<()> {
-        queue!(writer, MoveTo(0, 0))?;
-
-        for line in &self.lines {
-            queue!(writer, Print(line))?;
+        // Each row is moved to rather than reached with a line break, which
+        // would scroll the terminal when the last row has no bottom bar below
+        // it to spare.
+        for (row, line) in self.lines.iter().enumerate() {
+            queue!(writer, MoveTo(0, row as u16))?;
+            for span in line {
+                let mut content = span.text.as_str().stylize();
+                if let Some(color) = self.token_color(span.class) {
+                    content = content.with(color);
+                }
+                if span.selected {
+                    content = content.negative();
+                }
+                if span.class == TokenClass::CurrentLineNumber {
+                    content = content.bold();
+                }
+                queue!(writer, PrintStyledContent(content))?;
+            }
             queue!(writer, Clear(UntilNewLine))?;
-            queue!(writer, Print("\r\n"))?;
         }
 
         Ok(())
@@ -64,33 +85,55 @@ impl TextAreaComponent {
         Ok(())
     }
 
+    fn token_color(&self, class: TokenClass) -> Option<Color> {
+        match class {
+            TokenClass::Normal => None,
+            TokenClass::Keyword => Some(self.theme.keyword),
+            TokenClass::Type => Some(self.theme.type_),
+            TokenClass::String => Some(self.theme.string),
+            TokenClass::Comment => Some(self.theme.comment),
+            TokenClass::Number => Some(self.theme.number),
+            TokenClass::LineNumber => Some(self.theme.line_number),
+            TokenClass::CurrentLineNumber => Some(self.theme.current_line_number),
+        }
+    }
+
+    // Keys with bindings are handled by the keymap, this only gets the rest of
+    // them, which are typed into the buffer.
     pub fn process_event(&mut self, event: KeyEvent, queue: &mut MessageQueue) -> Result<()> {
-        use EditorControllerMessage::*;
         use KeyCode::*;
         use KeyModifiers as KM;
 
         let KeyEvent { code, modifiers } = event;
         let message = match (modifiers, code) {
-            (KM::NONE, Up) => MoveCursorUp,
-            (KM::NONE, Down) => MoveCursorDown,
-            (KM::NONE, Left) => MoveCursorLeft,
-            (KM::NONE, Right) => MoveCursorRight,
-
-            (KM::NONE, Home) => MoveCursorToLineStart,
-            (KM::NONE, End) => MoveCursorToLineEnd,
-
-            (KM::NONE, PageUp) => MoveOneViewUp,
-            (KM::NONE, PageDown) => MoveOneViewDown,
-
-            (KM::CONTROL, PageUp) => MoveCursorToBufferTop,
-            (KM::CONTROL, PageDown) => MoveCursorToBufferBottom,
-
-            (KM::NONE, Backspace) => RemoveCharBehind,
-            (KM::NONE, Delete) => RemoveCharInFront,
+            // Uppercase letters come with Shift held down.
+            (KM::NONE | KM::SHIFT, Char(c)) => EditorControllerMessage::InsertChar(c),
+            _ => return Ok(()),
+        };
 
-            (KM::NONE, Char(c)) => InsertChar(c),
-            (KM::NONE, Enter) => InsertLine,
+        queue.push_front(message);
+        Ok(())
+    }
 
+    // Clicking places the cursor, and dragging or Shift+clicking selects the
+    // text between it and the cursor.
+    pub fn process_mouse_event(
+        &mut self,
+        event: MouseEvent,
+        queue: &mut MessageQueue,
+    ) -> Result<()> {
+        use MouseEventKind::*;
+
+        let location = Location::new(event.row as usize, event.column as usize);
+        let is_shift_held = event.modifiers.contains(KeyModifiers::SHIFT);
+        let message = match event.kind {
+            Down(MouseButton::Left) if is_shift_held => {
+                EditorControllerMessage::SelectToViewLocation(location)
+            }
+            Down(MouseButton::Left) => EditorControllerMessage::MoveCursorToViewLocation(location),
+            Drag(MouseButton::Left) => EditorControllerMessage::SelectToViewLocation(location),
+            ScrollUp => EditorControllerMessage::ScrollView(-SCROLL_ROWS),
+            ScrollDown => EditorControllerMessage::ScrollView(SCROLL_ROWS),
             _ => return Ok(()),
         };
 
@@ -99,7 +142,7 @@ impl TextAreaComponent {
     }
 }
 
-fn get_editor_lines(editor: &Editor) -> Vec<String> {
+fn get_editor_lines(editor: &Editor) -> Vec<StyledLine> {
     editor.get_view_contents().collect()
 }
 